@@ -0,0 +1,28 @@
+use crate::vec3::Point3;
+
+// Axis aligned bounding box
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Aabb {
+    pub minimum: Point3,
+    pub maximum: Point3
+}
+
+impl Aabb {
+    pub fn new(minimum: Point3, maximum: Point3) -> Aabb {
+        Aabb { minimum, maximum }
+    }
+
+    pub fn surrounding_box(box0: &Aabb, box1: &Aabb) -> Aabb {
+        let small = Point3::new(
+            f64::min(box0.minimum.x, box1.minimum.x),
+            f64::min(box0.minimum.y, box1.minimum.y),
+            f64::min(box0.minimum.z, box1.minimum.z)
+        );
+        let big = Point3::new(
+            f64::max(box0.maximum.x, box1.maximum.x),
+            f64::max(box0.maximum.y, box1.maximum.y),
+            f64::max(box0.maximum.z, box1.maximum.z)
+        );
+        Aabb::new(small, big)
+    }
+}
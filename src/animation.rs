@@ -0,0 +1,356 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    ops::{Add, Mul, Sub},
+    str::SplitWhitespace,
+    sync::Arc
+};
+
+use crate::{camera::CameraSettings, sphere::Sphere, vec3::{Color, Vec3}};
+
+// Keyframed camera and objects, loaded from a text file with one key per line:
+//
+//     <channel> <frame> <value> [linear | bezier | catmull-rom] [in <value>] [out <value>]
+//
+// Channels are camera.position, camera.target (x y z), camera.fov (degrees),
+// camera.aperture and camera.focus-distance, and per object of the scene
+// object.<index>.translate (x y z), object.<index>.scale, object.<index>.albedo
+// (r g b), object.<index>.fuzz and object.<index>.ior. Negative indices count
+// from the end of the scene's object list, indices outside it are an error.
+// The interpolation applies to the segment starting at the key, linear if not
+// given. `in` and `out` are the Bezier handles around the key, by default the
+// value itself, which eases in and out. Text after '#' is a comment.
+
+// How a track gets from one key to the next
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    // Cubic with the out handle of the first key and the in handle of the second as control points
+    Bezier,
+    // Smooth curve through the keys, with tangents from the neighbouring keys
+    CatmullRom
+}
+
+impl Interpolation {
+    pub fn from_name(name: &str) -> Option<Interpolation> {
+        match name {
+            "linear" => Some(Interpolation::Linear),
+            "bezier" => Some(Interpolation::Bezier),
+            "catmull-rom" => Some(Interpolation::CatmullRom),
+            _ => None
+        }
+    }
+}
+
+// Values that can be keyed
+pub trait Value: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f64, Output = Self> {
+    const COMPONENTS: usize;
+
+    fn from_components(components: &[f64]) -> Self;
+}
+
+impl Value for f64 {
+    const COMPONENTS: usize = 1;
+
+    fn from_components(components: &[f64]) -> f64 {
+        components[0]
+    }
+}
+
+impl Value for Vec3 {
+    const COMPONENTS: usize = 3;
+
+    fn from_components(components: &[f64]) -> Vec3 {
+        Vec3::new(components[0], components[1], components[2])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Key<T> {
+    pub frame: f64,
+    pub value: T,
+    pub interpolation: Interpolation,
+    pub in_handle: T,
+    pub out_handle: T
+}
+
+// Keys of one channel, sorted by frame
+#[derive(Debug, Clone)]
+pub struct Track<T> {
+    keys: Vec<Key<T>>
+}
+
+impl<T: Value> Track<T> {
+    pub fn new() -> Track<T> {
+        Track { keys: Vec::new() }
+    }
+
+    pub fn insert(&mut self, key: Key<T>) -> Result<(), String> {
+        let index = self.keys.partition_point(|k| k.frame < key.frame);
+        if self.keys.get(index).is_some_and(|k| k.frame == key.frame) {
+            return Err(format!("two keys at frame {}", key.frame));
+        }
+        self.keys.insert(index, key);
+        Ok(())
+    }
+
+    // Value at `frame`, held constant before the first and after the last key
+    pub fn evaluate(&self, frame: f64) -> T {
+        let keys = &self.keys;
+        let last = keys.len() - 1;
+        if frame <= keys[0].frame {
+            return keys[0].value;
+        }
+        if frame >= keys[last].frame {
+            return keys[last].value;
+        }
+
+        let i = keys.partition_point(|k| k.frame <= frame) - 1;
+        let (k0, k1) = (&keys[i], &keys[i + 1]);
+        let span = k1.frame - k0.frame;
+        let t = (frame - k0.frame) / span;
+
+        match k0.interpolation {
+            Interpolation::Linear => k0.value + (k1.value - k0.value) * t,
+            Interpolation::Bezier => bezier(k0.value, k0.out_handle, k1.in_handle, k1.value, t),
+            Interpolation::CatmullRom => {
+                // Change per frame between the neighbours, one sided at the first and last key.
+                // Scaling by the segment length keeps the curve smooth when keys are unevenly spaced.
+                let tangent = |j: usize| {
+                    let (a, b) = (&keys[j.saturating_sub(1)], &keys[usize::min(j + 1, last)]);
+                    (b.value - a.value) * (span / (b.frame - a.frame))
+                };
+                hermite(k0.value, tangent(i), k1.value, tangent(i + 1), t)
+            }
+        }
+    }
+}
+
+fn bezier<T: Value>(p0: T, p1: T, p2: T, p3: T, t: f64) -> T {
+    let s = 1.0 - t;
+    p0 * (s * s * s) + p1 * (3.0 * s * s * t) + p2 * (3.0 * s * t * t) + p3 * (t * t * t)
+}
+
+fn hermite<T: Value>(p0: T, m0: T, p1: T, m1: T, t: f64) -> T {
+    let (t2, t3) = (t * t, t * t * t);
+    p0 * (2.0 * t3 - 3.0 * t2 + 1.0) + m0 * (t3 - 2.0 * t2 + t) + p1 * (3.0 * t2 - 2.0 * t3) + m1 * (t3 - t2)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Animation {
+    camera: CameraTracks,
+    objects: BTreeMap<i64, ObjectTracks>
+}
+
+#[derive(Debug, Clone, Default)]
+struct CameraTracks {
+    position: Option<Track<Vec3>>,
+    target: Option<Track<Vec3>>,
+    fov: Option<Track<f64>>,
+    aperture: Option<Track<f64>>,
+    focus_distance: Option<Track<f64>>
+}
+
+#[derive(Debug, Clone, Default)]
+struct ObjectTracks {
+    translate: Option<Track<Vec3>>,
+    scale: Option<Track<f64>>,
+    albedo: Option<Track<Color>>,
+    fuzz: Option<Track<f64>>,
+    ior: Option<Track<f64>>
+}
+
+impl Animation {
+    pub fn load(path: &str) -> Result<Animation, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("cannot read animation {}: {}", path, err))?;
+        Animation::parse(&text).map_err(|err| format!("{}: {}", path, err))
+    }
+
+    pub fn parse(text: &str) -> Result<Animation, String> {
+        let mut animation = Animation::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut tokens = line.split_whitespace();
+            if let Some(channel) = tokens.next() {
+                animation.add_key(channel, &mut tokens).map_err(|err| format!("line {}: {}", number + 1, err))?;
+            }
+        }
+        Ok(animation)
+    }
+
+    fn add_key(&mut self, channel: &str, tokens: &mut SplitWhitespace) -> Result<(), String> {
+        let parts: Vec<&str> = channel.split('.').collect();
+        match parts[..] {
+            ["camera", "position"] => insert(&mut self.camera.position, tokens),
+            ["camera", "target"] => insert(&mut self.camera.target, tokens),
+            ["camera", "fov"] => insert(&mut self.camera.fov, tokens),
+            ["camera", "aperture"] => insert(&mut self.camera.aperture, tokens),
+            ["camera", "focus-distance"] => insert(&mut self.camera.focus_distance, tokens),
+            ["object", index, property] => {
+                let index: i64 = index.parse().map_err(|_| format!("invalid object index {}", index))?;
+                let object = self.objects.entry(index).or_default();
+                match property {
+                    "translate" => insert(&mut object.translate, tokens),
+                    "scale" => insert(&mut object.scale, tokens),
+                    "albedo" => insert(&mut object.albedo, tokens),
+                    "fuzz" => insert(&mut object.fuzz, tokens),
+                    "ior" => insert(&mut object.ior, tokens),
+                    _ => Err(format!("unknown object property {}", property))
+                }
+            }
+            _ => Err(format!("unknown channel {}", channel))
+        }
+    }
+
+    // Camera at `frame`: keyed values replace the ones of `base`
+    pub fn camera(&self, base: CameraSettings, frame: f64) -> CameraSettings {
+        let tracks = &self.camera;
+        CameraSettings {
+            look_from: value_at(&tracks.position, frame, base.look_from),
+            look_at: value_at(&tracks.target, frame, base.look_at),
+            vfov: value_at(&tracks.fov, frame, base.vfov),
+            aperture: value_at(&tracks.aperture, frame, base.aperture),
+            focus_dist: value_at(&tracks.focus_distance, frame, base.focus_dist),
+            ..base
+        }
+    }
+
+    // Moves, scales and changes the materials of the keyed objects to their state at `frame`
+    pub fn animate_objects(&self, objects: &mut [Sphere], frame: f64) -> Result<(), String> {
+        let count = objects.len();
+        for (&index, tracks) in &self.objects {
+            let from_start = if index < 0 { count as i64 + index } else { index };
+            let sphere = usize::try_from(from_start).ok().and_then(|i| objects.get_mut(i))
+                .ok_or(format!("animation key for object {} is out of range, the scene has {} objects", index, count))?;
+
+            sphere.center += value_at(&tracks.translate, frame, Vec3::default());
+            sphere.radius *= value_at(&tracks.scale, frame, 1.0);
+
+            if tracks.albedo.is_some() || tracks.fuzz.is_some() || tracks.ior.is_some() {
+                let evaluate = |track: &Option<Track<f64>>| track.as_ref().map(|t| t.evaluate(frame));
+                let albedo = tracks.albedo.as_ref().map(|t| t.evaluate(frame));
+                let material = sphere.material.with_parameters(albedo, evaluate(&tracks.fuzz), evaluate(&tracks.ior));
+                sphere.material = Arc::new(material);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn value_at<T: Value>(track: &Option<Track<T>>, frame: f64, default: T) -> T {
+    track.as_ref().map_or(default, |track| track.evaluate(frame))
+}
+
+fn insert<T: Value>(track: &mut Option<Track<T>>, tokens: &mut SplitWhitespace) -> Result<(), String> {
+    let frame = number(tokens.next())?;
+    let value = values(tokens)?;
+    let interpolation = match tokens.next() {
+        Some(name) => Interpolation::from_name(name)
+            .ok_or(format!("unknown interpolation {} (expected linear, bezier or catmull-rom)", name))?,
+        None => Interpolation::Linear
+    };
+
+    let mut key = Key { frame, value, interpolation, in_handle: value, out_handle: value };
+    while let Some(token) = tokens.next() {
+        match token {
+            "in" => key.in_handle = values(tokens)?,
+            "out" => key.out_handle = values(tokens)?,
+            _ => return Err(format!("unexpected {}", token))
+        }
+    }
+    track.get_or_insert_with(Track::new).insert(key)
+}
+
+fn values<T: Value>(tokens: &mut SplitWhitespace) -> Result<T, String> {
+    let components = (0..T::COMPONENTS).map(|_| number(tokens.next())).collect::<Result<Vec<_>, _>>()?;
+    Ok(T::from_components(&components))
+}
+
+fn number(token: Option<&str>) -> Result<f64, String> {
+    let token = token.ok_or("missing value")?;
+    token.parse().map_err(|_| format!("invalid number {}", token))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Animation, Interpolation, Key, Track};
+    use crate::{material::{Material, lambertian::Lambertian}, sphere::Sphere, vec3::{Color, Point3}};
+
+    fn track(keys: &[(f64, f64)], interpolation: Interpolation) -> Track<f64> {
+        let mut track = Track::new();
+        for &(frame, value) in keys {
+            track.insert(Key { frame, value, interpolation, in_handle: value, out_handle: value }).unwrap();
+        }
+        track
+    }
+
+    #[test]
+    fn interpolations_pass_through_keys() {
+        let keys = [(0.0, 1.0), (10.0, 3.0), (15.0, -2.0), (30.0, 4.0)];
+        for interpolation in [Interpolation::Linear, Interpolation::Bezier, Interpolation::CatmullRom] {
+            let track = track(&keys, interpolation);
+            for (frame, value) in keys {
+                assert!((track.evaluate(frame) - value).abs() < 1e-12, "{:?}", interpolation);
+            }
+            assert_eq!(track.evaluate(-5.0), 1.0);
+            assert_eq!(track.evaluate(40.0), 4.0);
+        }
+    }
+
+    #[test]
+    fn linear_and_eased_midpoints() {
+        let linear = track(&[(0.0, 0.0), (10.0, 1.0)], Interpolation::Linear);
+        let bezier = track(&[(0.0, 0.0), (10.0, 1.0)], Interpolation::Bezier);
+
+        assert!((linear.evaluate(2.5) - 0.25).abs() < 1e-12);
+        assert!((bezier.evaluate(5.0) - 0.5).abs() < 1e-12);
+        // Default handles ease in: slower than linear at the start
+        assert!(bezier.evaluate(2.5) < 0.25);
+    }
+
+    #[test]
+    fn catmull_rom_keeps_a_line_straight() {
+        let track = track(&[(0.0, 0.0), (2.0, 2.0), (5.0, 5.0), (6.0, 6.0)], Interpolation::CatmullRom);
+        for frame in [0.5, 1.0, 3.3, 5.5] {
+            assert!((track.evaluate(frame) - frame).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn parses_keys() {
+        let text = "
+            # Turntable
+            camera.position 0 13 2 3 catmull-rom
+            camera.position 24 -13 2 3
+            object.-1.fuzz 0 0.0 bezier out 0.5
+            object.-1.fuzz 24 0.5
+        ";
+        let animation = Animation::parse(text).unwrap();
+
+        let position = animation.camera.position.as_ref().unwrap();
+        assert_eq!(position.evaluate(12.0).x, 0.0);
+        assert!(animation.objects[&-1].fuzz.is_some());
+
+        assert!(Animation::parse("camera.fov 0 20\ncamera.fov 0 30").is_err());
+        assert!(Animation::parse("camera.target 0 1 2").is_err());
+        assert!(Animation::parse("object.x.scale 0 1").is_err());
+    }
+
+    #[test]
+    fn keys_past_the_scene_are_an_error() {
+        let gray = Arc::new(Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let mut objects = vec![Sphere::new(Point3::default(), 1.0, &gray), Sphere::new(Point3::default(), 1.0, &gray)];
+
+        let animation = Animation::parse("object.-2.scale 0 2").unwrap();
+        assert!(animation.animate_objects(&mut objects, 0.0).is_ok());
+        assert_eq!(objects[0].radius, 2.0);
+
+        for index in [2, -3] {
+            let animation = Animation::parse(&format!("object.{}.scale 0 2", index)).unwrap();
+            let error = animation.animate_objects(&mut objects, 0.0).unwrap_err();
+            assert!(error.contains(&format!("object {} ", index)), "{}", error);
+        }
+    }
+}
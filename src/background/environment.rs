@@ -0,0 +1,142 @@
+use std::f64::consts::PI;
+
+use crate::{distribution::Distribution2D, util::degrees_to_radians, vec3::{Color, Vec3}};
+
+use super::{lat_long_direction, lat_long_pdf, lat_long_point};
+
+// Latitude-longitude HDR image around the scene, laid out like the
+// equirectangular camera's image: -z in the middle, +y at the top. Directions
+// are sampled in proportion to the image's luminance so that small bright
+// areas like the sun are found by direct lighting, not by chance.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    // Turn of the map about the vertical axis in radians
+    rotation: f64,
+    intensity: f64,
+    distribution: Distribution2D
+}
+
+impl EnvironmentMap {
+    // Reads a Radiance .hdr or OpenEXR file, or any other format the image crate knows
+    pub fn load(path: &str, rotation: f64, intensity: f64) -> Result<EnvironmentMap, String> {
+        let image = image::open(path).map_err(|err| format!("cannot read environment map {}: {}", path, err))?.to_rgb32f();
+        let (width, height) = image.dimensions();
+        let pixels = image.pixels().map(|p| Color::new(p.0[0] as f64, p.0[1] as f64, p.0[2] as f64)).collect();
+        Ok(EnvironmentMap::new(pixels, width as usize, height as usize, rotation, intensity))
+    }
+
+    pub fn new(pixels: Vec<Color>, width: usize, height: usize, rotation: f64, intensity: f64) -> EnvironmentMap {
+        // Rows near the poles cover less of the sphere
+        let function: Vec<f64> = pixels.iter().enumerate().map(|(i, color)| {
+            let theta = PI * ((i / width) as f64 + 0.5) / height as f64;
+            color.luminance().max(0.0) * f64::sin(theta)
+        }).collect();
+        let distribution = Distribution2D::new(&function, width, height);
+
+        EnvironmentMap { width, height, pixels, rotation: degrees_to_radians(rotation), intensity, distribution }
+    }
+
+    pub fn radiance(&self, direction: &Vec3) -> Color {
+        let (x, y) = lat_long_point(direction, self.rotation);
+        let column = usize::min((x * self.width as f64) as usize, self.width - 1);
+        let row = usize::min((y * self.height as f64) as usize, self.height - 1);
+        self.intensity * self.pixels[row * self.width + column]
+    }
+
+    pub fn sample(&self, u: (f64, f64)) -> (Vec3, Color, f64) {
+        let (p, pdf) = self.distribution.sample(u);
+        let direction = lat_long_direction(p, self.rotation);
+        (direction, self.radiance(&direction), lat_long_pdf(p, pdf))
+    }
+
+    pub fn pdf(&self, direction: &Vec3) -> f64 {
+        let p = lat_long_point(direction, self.rotation);
+        lat_long_pdf(p, self.distribution.pdf(p))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{f64::consts::PI, sync::Arc};
+
+    use super::EnvironmentMap;
+    use crate::{
+        background::Background, hittable_list::HittableList, light::tree::LightTree,
+        material::{Material, lambertian::Lambertian}, ray::Ray, render::ray_color,
+        sampler::{PixelSampler, Sampler}, scene::Scene, sphere::Sphere, vec3::{Color, Point3, Vec3}
+    };
+
+    // Dim map with one bright pixel
+    fn sun_map(rotation: f64) -> EnvironmentMap {
+        let (width, height) = (16, 8);
+        let mut pixels = vec![Color::new(0.1, 0.1, 0.1); width * height];
+        pixels[2 * width + 12] = Color::new(1000.0, 900.0, 800.0);
+        EnvironmentMap::new(pixels, width, height, rotation, 2.0)
+    }
+
+    #[test]
+    fn samples_find_the_sun() {
+        let map = sun_map(0.0);
+        let mut bright = 0;
+        for i in 0..100 {
+            let (direction, radiance, pdf) = map.sample(((i as f64 + 0.5) / 100.0, 0.37));
+            assert!((map.pdf(&direction) - pdf).abs() < 1e-9 * pdf);
+            assert_eq!(radiance, map.radiance(&direction));
+            if radiance.x > 1.0 {
+                bright += 1;
+            }
+        }
+        assert!(bright > 90);
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let map = sun_map(30.0);
+        let n = 400;
+        let mut integral = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                // Uniform directions over the sphere
+                let z = 1.0 - 2.0 * (i as f64 + 0.5) / n as f64;
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n as f64;
+                let r = f64::sqrt(1.0 - z * z);
+                integral += map.pdf(&Vec3::new(r * f64::cos(phi), r * f64::sin(phi), z));
+            }
+        }
+        integral *= 4.0 * PI / (n * n) as f64;
+        assert!((integral - 1.0).abs() < 0.02, "{}", integral);
+    }
+
+    #[test]
+    fn rotation_turns_the_map() {
+        let map = sun_map(90.0);
+        // The middle of the image is in front of the camera until the map turns
+        assert_eq!(sun_map(0.0).radiance(&Vec3::new(0.0, 0.0, -1.0)), map.radiance(&Vec3::new(1.0, 0.0, 0.0)));
+        assert_eq!(map.radiance(&Vec3::new(0.0, 0.0, -1.0)), Color::new(0.2, 0.2, 0.2));
+    }
+
+    #[test]
+    fn gray_sphere_in_a_white_environment() {
+        // Furnace test: a convex sphere lit evenly from everywhere reflects its albedo,
+        // whichever way the light was found
+        let gray = Arc::new(Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, &gray)));
+        let white = EnvironmentMap::new(vec![Color::new(1.0, 1.0, 1.0); 32], 8, 4, 0.0, 1.0);
+        let background = Background::Environment(Arc::new(white));
+        let scene = Scene { world, emitters: LightTree::default() };
+
+        let samples = 4000;
+        let mut sum = Color::default();
+        for index in 0..samples {
+            let mut sampler = PixelSampler::new(Sampler::Independent, 3, 0, 0, 1, index, samples);
+            let r = Ray::new(Point3::new(0.3, 0.2, 5.0), Vec3::new(0.0, 0.0, -1.0));
+            sum += ray_color(&r, &scene, &[], &background, 8, &mut sampler);
+        }
+        let mean = sum.luminance() / samples as f64;
+        assert!((mean - 0.5).abs() < 0.02, "{}", mean);
+    }
+}
@@ -0,0 +1,82 @@
+use std::{f64::consts::PI, sync::Arc};
+
+use crate::vec3::{Color, Vec3};
+
+use self::{environment::EnvironmentMap, sky::Sky};
+
+pub mod environment;
+pub mod sky;
+
+// What a ray sees when it leaves the scene
+#[derive(Clone, Default)]
+pub enum Background {
+    // White at the horizon fading to blue overhead
+    #[default]
+    Gradient,
+    Environment(Arc<EnvironmentMap>),
+    Sky(Arc<Sky>)
+}
+
+impl Background {
+    pub fn radiance(&self, direction: &Vec3) -> Color {
+        match self {
+            Background::Gradient => {
+                let unit_direction = direction.unit_vector();
+                let t = 0.5*(unit_direction.y + 1.0);
+                (1.0-t)*Color::new(1.0, 1.0, 1.0) + t*Color::new(0.5, 0.7, 1.0)
+            }
+            Background::Environment(environment) => environment.radiance(direction),
+            Background::Sky(sky) => sky.radiance(direction)
+        }
+    }
+
+    // Whether it is sampled as a light, rays that escape only carry
+    // part of its radiance then
+    pub fn is_sampled(&self) -> bool {
+        !matches!(self, Background::Gradient)
+    }
+
+    // Direction toward the background, its radiance and the density per solid angle
+    pub fn sample(&self, u: (f64, f64)) -> Option<(Vec3, Color, f64)> {
+        match self {
+            Background::Gradient => None,
+            Background::Environment(environment) => Some(environment.sample(u)),
+            Background::Sky(sky) => Some(sky.sample(u))
+        }
+    }
+
+    // Density per solid angle of sampling this direction
+    pub fn pdf(&self, direction: &Vec3) -> f64 {
+        match self {
+            Background::Gradient => 0.0,
+            Background::Environment(environment) => environment.pdf(direction),
+            Background::Sky(sky) => sky.pdf(direction)
+        }
+    }
+}
+
+// Point on a latitude-longitude image in [0, 1)^2, y down from the top, seeing
+// the direction: -z is in the middle and +y at the top until the image is
+// turned by `rotation` radians about the vertical
+fn lat_long_point(direction: &Vec3, rotation: f64) -> (f64, f64) {
+    let d = direction.unit_vector();
+    let longitude = f64::atan2(d.x, -d.z) - rotation;
+    let latitude = f64::asin(d.y.clamp(-1.0, 1.0));
+    ((longitude / (2.0 * PI) + 0.5).rem_euclid(1.0), 0.5 - latitude / PI)
+}
+
+fn lat_long_direction(p: (f64, f64), rotation: f64) -> Vec3 {
+    let longitude = (p.0 - 0.5) * 2.0 * PI + rotation;
+    let latitude = (0.5 - p.1) * PI;
+    Vec3::new(
+        f64::cos(latitude) * f64::sin(longitude),
+        f64::sin(latitude),
+        -f64::cos(latitude) * f64::cos(longitude)
+    )
+}
+
+// Converts a density over a latitude-longitude image to one over directions
+fn lat_long_pdf(p: (f64, f64), pdf: f64) -> f64 {
+    let sin_theta = f64::sin(PI * p.1);
+    if sin_theta <= 0.0 { 0.0 } else { pdf / (2.0 * PI * PI * sin_theta) }
+}
@@ -0,0 +1,289 @@
+use std::f64::consts::PI;
+
+use crate::{distribution::Distribution2D, util::degrees_to_radians, vec3::{Color, Vec3}};
+
+use super::{lat_long_direction, lat_long_pdf, lat_long_point};
+
+// Angular radius of the sun disk
+const SUN_RADIUS: f64 = 0.00465;
+// Luminance of the sun outside the atmosphere in kcd/m^2, the unit of the sky model
+const SUN_LUMINANCE: f64 = 2.0e6;
+// Size of the table the sky is sampled from
+const TABLE_WIDTH: usize = 128;
+const TABLE_HEIGHT: usize = 64;
+
+// Preetham et al. daylight: the clear sky for a sun position and turbidity
+// (the haziness, 2 is very clear and 10 hazy), with the sun disk seen through
+// the atmosphere. Below the horizon is a diffuse ground lit by both. Directions
+// have +y up, north toward -z and east toward +x.
+pub struct Sky {
+    sun_direction: Vec3,
+    sun_radiance: Color,
+    // Perez distribution coefficients and zenith value of Y, x and y
+    perez: [[f64; 5]; 3],
+    zenith: [f64; 3],
+    ground: Color,
+    intensity: f64,
+    distribution: Distribution2D,
+    // Chance of sampling the sun rather than the sky
+    sun_probability: f64
+}
+
+impl Sky {
+    // The sun's elevation above the horizon and azimuth clockwise from north are
+    // in degrees. Radiance is in kcd/m^2 times `intensity`.
+    pub fn new(turbidity: f64, ground_albedo: f64, elevation: f64, azimuth: f64, intensity: f64) -> Sky {
+        let (elevation, azimuth) = (degrees_to_radians(elevation), degrees_to_radians(azimuth));
+        let sun_direction = Vec3::new(
+            f64::sin(azimuth) * f64::cos(elevation),
+            f64::sin(elevation),
+            -f64::cos(azimuth) * f64::cos(elevation)
+        );
+        let theta_s = PI / 2.0 - elevation;
+        let t = turbidity;
+
+        let perez = [
+            [0.1787*t - 1.4630, -0.3554*t + 0.4275, -0.0227*t + 5.3251, 0.1206*t - 2.5771, -0.0670*t + 0.3703],
+            [-0.0193*t - 0.2592, -0.0665*t + 0.0008, -0.0004*t + 0.2125, -0.0641*t - 0.8989, -0.0033*t + 0.0452],
+            [-0.0167*t - 0.2608, -0.0950*t + 0.0092, -0.0079*t + 0.2102, -0.0441*t - 1.6537, -0.0109*t + 0.0529]
+        ];
+        let chi = (4.0/9.0 - t/120.0) * (PI - 2.0*theta_s);
+        let cubic = |c: [f64; 4]| c[0]*theta_s.powi(3) + c[1]*theta_s.powi(2) + c[2]*theta_s + c[3];
+        let zenith = [
+            (4.0453*t - 4.9710) * f64::tan(chi) - 0.2155*t + 2.4192,
+            t*t*cubic([0.00166, -0.00375, 0.00209, 0.0]) + t*cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+                + cubic([0.11693, -0.21196, 0.06052, 0.25886]),
+            t*t*cubic([0.00275, -0.00610, 0.00317, 0.0]) + t*cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+                + cubic([0.15346, -0.26756, 0.06670, 0.26688])
+        ];
+
+        let mut sky = Sky {
+            sun_direction,
+            sun_radiance: SUN_LUMINANCE * intensity * sun_transmittance(theta_s, turbidity),
+            perez,
+            zenith,
+            ground: Color::default(),
+            intensity,
+            distribution: Distribution2D::new(&[0.0], 1, 1),
+            sun_probability: 0.0
+        };
+
+        // The ground reflects the light falling on it from the sky and the sun
+        let sun_irradiance = sky.sun_radiance * (PI * SUN_RADIUS * SUN_RADIUS) * f64::max(sun_direction.y, 0.0);
+        let mut sky_irradiance = Color::default();
+        let mut function = Vec::with_capacity(TABLE_WIDTH * TABLE_HEIGHT);
+        for row in 0..TABLE_HEIGHT {
+            for column in 0..TABLE_WIDTH {
+                let p = ((column as f64 + 0.5) / TABLE_WIDTH as f64, (row as f64 + 0.5) / TABLE_HEIGHT as f64);
+                let direction = lat_long_direction(p, 0.0);
+                let radiance = sky.sky_radiance(&direction);
+                let solid_angle = 2.0 * PI * PI * f64::sin(PI * p.1) / (TABLE_WIDTH * TABLE_HEIGHT) as f64;
+                sky_irradiance += solid_angle * f64::max(direction.y, 0.0) * radiance;
+                function.push(radiance.luminance() * f64::sin(PI * p.1));
+            }
+        }
+        sky.ground = ground_albedo / PI * (sky_irradiance + sun_irradiance);
+
+        // The ground's share of the table is only known now, it is the bottom half
+        for (i, f) in function.iter_mut().enumerate().skip(TABLE_WIDTH * TABLE_HEIGHT / 2) {
+            let y = ((i / TABLE_WIDTH) as f64 + 0.5) / TABLE_HEIGHT as f64;
+            *f = sky.ground.luminance() * f64::sin(PI * y);
+        }
+        sky.distribution = Distribution2D::new(&function, TABLE_WIDTH, TABLE_HEIGHT);
+
+        // Pick the sun in proportion to its power
+        let sky_power = 2.0 * PI * PI * sky.distribution.integral();
+        let sun_power = sky.sun_radiance.luminance() * 2.0 * PI * (1.0 - f64::cos(SUN_RADIUS));
+        sky.sun_probability = if sun_power + sky_power > 0.0 { sun_power / (sun_power + sky_power) } else { 0.0 };
+        sky
+    }
+
+    pub fn radiance(&self, direction: &Vec3) -> Color {
+        let d = direction.unit_vector();
+        if Vec3::dot(&d, &self.sun_direction) >= f64::cos(SUN_RADIUS) {
+            self.sky_radiance(&d) + self.sun_radiance
+        } else {
+            self.sky_radiance(&d)
+        }
+    }
+
+    pub fn sample(&self, u: (f64, f64)) -> (Vec3, Color, f64) {
+        let direction = if u.0 < self.sun_probability {
+            Vec3::random_in_cone(&self.sun_direction, f64::cos(SUN_RADIUS), (u.0 / self.sun_probability, u.1))
+        } else {
+            let u0 = (u.0 - self.sun_probability) / (1.0 - self.sun_probability);
+            let (p, _) = self.distribution.sample((u0, u.1));
+            lat_long_direction(p, 0.0)
+        };
+        (direction, self.radiance(&direction), self.pdf(&direction))
+    }
+
+    pub fn pdf(&self, direction: &Vec3) -> f64 {
+        let d = direction.unit_vector();
+        let p = lat_long_point(&d, 0.0);
+        let mut pdf = (1.0 - self.sun_probability) * lat_long_pdf(p, self.distribution.pdf(p));
+        if Vec3::dot(&d, &self.sun_direction) >= f64::cos(SUN_RADIUS) {
+            pdf += self.sun_probability / (2.0 * PI * (1.0 - f64::cos(SUN_RADIUS)));
+        }
+        pdf
+    }
+
+    // The sky without the sun disk
+    fn sky_radiance(&self, d: &Vec3) -> Color {
+        if d.y < 0.0 {
+            return self.ground;
+        }
+        // Perez et al. is made for the sky above the horizon and blows up at it
+        let cos_theta = f64::max(d.y, 0.01);
+        let cos_gamma = Vec3::dot(d, &self.sun_direction).clamp(-1.0, 1.0);
+        let gamma = f64::acos(cos_gamma);
+        let theta_s = f64::acos(self.sun_direction.y.clamp(-1.0, 1.0));
+
+        let perez = |c: &[f64; 5], cos_theta: f64, gamma: f64| {
+            (1.0 + c[0]*f64::exp(c[1]/cos_theta)) * (1.0 + c[2]*f64::exp(c[3]*gamma) + c[4]*f64::cos(gamma).powi(2))
+        };
+        let [big_y, x, y]: [f64; 3] = std::array::from_fn(|i| {
+            self.zenith[i] * perez(&self.perez[i], cos_theta, gamma) / perez(&self.perez[i], 1.0, theta_s)
+        });
+
+        self.intensity * xyy_to_rgb(x, y, big_y)
+    }
+}
+
+// Fraction of sunlight reaching the ground through Rayleigh scattering and
+// haze, with the sun's spectrum cut down to one wavelength per channel
+fn sun_transmittance(theta_s: f64, turbidity: f64) -> Color {
+    // Relative optical mass of the air the light goes through (Kasten)
+    let zenith_degrees = theta_s.to_degrees().min(93.885 - 1e-3);
+    let mass = 1.0 / (f64::cos(theta_s) + 0.15 * f64::powf(93.885 - zenith_degrees, -1.253));
+    // Angstrom's haze coefficients
+    let beta = 0.04608 * turbidity - 0.04586;
+    let alpha = 1.3;
+
+    let transmittance = |wavelength: f64| {
+        let rayleigh = f64::exp(-0.008735 * f64::powf(wavelength, -4.08) * mass);
+        let aerosol = f64::exp(-beta * f64::powf(wavelength, -alpha) * mass);
+        rayleigh * aerosol
+    };
+    // Micrometers
+    Color::new(transmittance(0.68), transmittance(0.55), transmittance(0.44))
+}
+
+// CIE xyY to linear sRGB, negative values clipped
+fn xyy_to_rgb(x: f64, y: f64, big_y: f64) -> Color {
+    if y <= 0.0 {
+        return Color::default();
+    }
+    let big_x = x / y * big_y;
+    let big_z = (1.0 - x - y) / y * big_y;
+    Color::new(
+        f64::max(0.0, 3.2406*big_x - 1.5372*big_y - 0.4986*big_z),
+        f64::max(0.0, -0.9689*big_x + 1.8758*big_y + 0.0415*big_z),
+        f64::max(0.0, 0.0557*big_x - 0.2040*big_y + 1.0570*big_z)
+    )
+}
+
+// Elevation and azimuth of the sun in degrees, azimuth clockwise from north, at
+// a UTC time of day in hours on a date, seen from a latitude and longitude in
+// degrees (east positive). Accurate to about a degree (NOAA's approximation).
+pub fn sun_position(year: i32, month: u32, day: u32, hours: f64, latitude: f64, longitude: f64) -> (f64, f64) {
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let month_lengths = [31, if leap { 29 } else { 28 }, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    let day_of_year = month_lengths[..(month as usize - 1)].iter().sum::<u32>() + day;
+    let days_in_year = if leap { 366.0 } else { 365.0 };
+
+    let g = 2.0 * PI / days_in_year * (day_of_year as f64 - 1.0 + (hours - 12.0) / 24.0);
+    let equation_of_time = 229.18 * (0.000075 + 0.001868*f64::cos(g) - 0.032077*f64::sin(g)
+        - 0.014615*f64::cos(2.0*g) - 0.040849*f64::sin(2.0*g));
+    let declination = 0.006918 - 0.399912*f64::cos(g) + 0.070257*f64::sin(g) - 0.006758*f64::cos(2.0*g)
+        + 0.000907*f64::sin(2.0*g) - 0.002697*f64::cos(3.0*g) + 0.00148*f64::sin(3.0*g);
+
+    // Solar time in minutes, then the hour angle
+    let solar_time = hours * 60.0 + equation_of_time + 4.0 * longitude;
+    let hour_angle = degrees_to_radians(solar_time / 4.0 - 180.0);
+    let latitude = degrees_to_radians(latitude);
+
+    let sin_elevation = f64::sin(latitude)*f64::sin(declination) + f64::cos(latitude)*f64::cos(declination)*f64::cos(hour_angle);
+    let elevation = f64::asin(sin_elevation.clamp(-1.0, 1.0));
+    // Measured from south toward west, then turned to start at north
+    let azimuth = f64::atan2(
+        f64::sin(hour_angle),
+        f64::cos(hour_angle)*f64::sin(latitude) - f64::tan(declination)*f64::cos(latitude)
+    ) + PI;
+    (elevation.to_degrees(), azimuth.to_degrees().rem_euclid(360.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::{SUN_RADIUS, Sky, sun_position};
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let sky = Sky::new(3.0, 0.3, 30.0, 120.0, 1.0);
+        let n = 400;
+        let mut integral = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let z = 1.0 - 2.0 * (i as f64 + 0.5) / n as f64;
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n as f64;
+                let r = f64::sqrt(1.0 - z * z);
+                let direction = Vec3::new(r * f64::cos(phi), r * f64::sin(phi), z);
+                // The sun disk is too small for the grid, it adds its share exactly
+                if Vec3::dot(&direction, &sky.sun_direction) < f64::cos(SUN_RADIUS) {
+                    integral += sky.pdf(&direction);
+                }
+            }
+        }
+        integral = integral * 4.0 * PI / (n * n) as f64 + sky.sun_probability;
+        assert!((integral - 1.0).abs() < 0.02, "{}", integral);
+    }
+
+    #[test]
+    fn sampling_finds_the_sun() {
+        let sky = Sky::new(2.5, 0.3, 40.0, 200.0, 1.0);
+        let samples = 200;
+        let mut sun = 0;
+        for i in 0..samples {
+            let (direction, radiance, pdf) = sky.sample(((i as f64 + 0.5) / samples as f64, 0.3));
+            assert!((sky.pdf(&direction) - pdf).abs() < 1e-9 * pdf);
+            if Vec3::dot(&direction, &sky.sun_direction) > f64::cos(SUN_RADIUS) {
+                assert_eq!(radiance, sky.radiance(&direction));
+                sun += 1;
+            }
+        }
+        // The tiny sun gives a large share of the light
+        assert!(sun > samples / 4, "{}", sun);
+    }
+
+    #[test]
+    fn low_sun_is_redder() {
+        let high = Sky::new(3.0, 0.3, 60.0, 0.0, 1.0).sun_radiance;
+        let low = Sky::new(3.0, 0.3, 5.0, 0.0, 1.0).sun_radiance;
+        assert!(low.z / low.x < high.z / high.x);
+        assert!(low.luminance() < high.luminance());
+    }
+
+    #[test]
+    fn sky_is_bluer_than_the_horizon() {
+        let sky = Sky::new(3.0, 0.3, 45.0, 180.0, 1.0);
+        let zenith = sky.radiance(&Vec3::new(0.0, 1.0, 0.0));
+        let horizon = sky.radiance(&Vec3::new(0.0, 0.05, -1.0));
+        assert!(zenith.z > zenith.x);
+        assert!(zenith.z / zenith.x > horizon.z / horizon.x);
+    }
+
+    #[test]
+    fn summer_noon_at_fifty_two_north() {
+        // At noon the sun stands due south, 90 - 52 + 23.44 degrees up
+        let (elevation, azimuth) = sun_position(2024, 6, 21, 12.0, 52.0, 0.0);
+        assert!((elevation - 61.44).abs() < 0.5, "{}", elevation);
+        assert!((azimuth - 180.0).abs() < 1.0, "{}", azimuth);
+
+        // Morning sun is in the east
+        let (elevation, azimuth) = sun_position(2024, 6, 21, 7.0, 52.0, 0.0);
+        assert!(elevation > 0.0 && azimuth > 45.0 && azimuth < 135.0, "{} {}", elevation, azimuth);
+    }
+}
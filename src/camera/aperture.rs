@@ -0,0 +1,144 @@
+use std::{f64::consts::PI, sync::Arc};
+
+use crate::{distribution::Distribution2D, vec3::Vec3};
+
+// Shape of the lens opening, which is the shape out of focus highlights take
+#[derive(Debug, Clone, Default)]
+pub enum ApertureShape {
+    #[default]
+    Disk,
+    // Regular polygon formed by the diaphragm blades, rotated by `rotation` radians
+    Polygon { blades: u32, rotation: f64 },
+    // Grayscale image over the square around the lens, brighter texels let more light through
+    Mask(Arc<Distribution2D>)
+}
+
+impl ApertureShape {
+    pub fn load_mask(path: &str) -> Result<ApertureShape, String> {
+        let image = image::open(path).map_err(|err| format!("cannot read aperture mask {}: {}", path, err))?.to_luma8();
+        let (width, height) = image.dimensions();
+        let transmission: Vec<f64> = image.pixels().map(|p| p.0[0] as f64 / 255.0).collect();
+        if transmission.iter().all(|&t| t == 0.0) {
+            return Err(format!("aperture mask {} is black", path));
+        }
+        Ok(ApertureShape::Mask(Arc::new(Distribution2D::new(&transmission, width as usize, height as usize))))
+    }
+
+    // Point in the aperture, which fits the unit circle (the polygon and disk) or square (a mask)
+    fn sample(&self, u: (f64, f64)) -> (f64, f64) {
+        match self {
+            ApertureShape::Disk => {
+                let p = Vec3::random_in_unit_disk(u);
+                (p.x, p.y)
+            }
+            ApertureShape::Polygon { blades, rotation } => {
+                // Pick one of the triangles between the center and two neighbouring corners
+                let n = *blades as f64;
+                let scaled = u.0 * n;
+                let i = f64::min(scaled.floor(), n - 1.0);
+                let corner = |k: f64| {
+                    let angle = rotation + 2.0 * PI * k / n;
+                    (angle.cos(), angle.sin())
+                };
+                let (a, b) = (corner(i), corner(i + 1.0));
+
+                // Uniform in the triangle
+                let r = f64::sqrt(scaled - i);
+                let (wa, wb) = (r * (1.0 - u.1), r * u.1);
+                (wa * a.0 + wb * b.0, wa * a.1 + wb * b.1)
+            }
+            ApertureShape::Mask(distribution) => {
+                let ((x, y), _) = distribution.sample(u);
+                (2.0 * x - 1.0, 1.0 - 2.0 * y)
+            }
+        }
+    }
+}
+
+// Lens effects on out of focus light
+#[derive(Debug, Clone)]
+pub struct Bokeh {
+    pub shape: ApertureShape,
+    // Horizontal squeeze of an anamorphic lens, which stretches bokeh vertically
+    pub squeeze: f64,
+    // Optical vignetting: how far the lens barrel cuts into the aperture towards
+    // the corners, where bokeh becomes cat's eye shaped. 0 turns it off.
+    pub vignetting: f64
+}
+
+impl Default for Bokeh {
+    fn default() -> Self {
+        Bokeh { shape: ApertureShape::Disk, squeeze: 1.0, vignetting: 0.0 }
+    }
+}
+
+impl Bokeh {
+    // Point on a lens of `lens_radius` in units of that radius for a ray towards
+    // image position (s, t), or None when the lens barrel blocks it. Blocked
+    // samples still count towards the pixel, as black, which is what darkens the
+    // corners. A pinhole has no barrel to block anything.
+    pub fn sample_lens(&self, u: (f64, f64), s: f64, t: f64, aspect_ratio: f64, lens_radius: f64) -> Option<(f64, f64)> {
+        let (x, y) = self.shape.sample(u);
+
+        // The barrel's opening, seen from off axis, is a circle shifted towards the image corner
+        if self.vignetting > 0.0 && lens_radius > 0.0 {
+            let diagonal = f64::sqrt(aspect_ratio * aspect_ratio + 1.0);
+            let cx = self.vignetting * (2.0 * s - 1.0) * aspect_ratio / diagonal;
+            let cy = self.vignetting * (2.0 * t - 1.0) / diagonal;
+            if (x - cx) * (x - cx) + (y - cy) * (y - cy) > 1.0 {
+                return None;
+            }
+        }
+        Some((x / self.squeeze, y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::{ApertureShape, Bokeh};
+
+    fn samples() -> impl Iterator<Item = (f64, f64)> {
+        (0..32).flat_map(|i| (0..32).map(move |j| ((i as f64 + 0.5) / 32.0, (j as f64 + 0.5) / 32.0)))
+    }
+
+    #[test]
+    fn polygon_samples_stay_inside() {
+        let bokeh = Bokeh { shape: ApertureShape::Polygon { blades: 5, rotation: 0.3 }, ..Bokeh::default() };
+        // Inscribed circle of the pentagon
+        let apothem = f64::cos(PI / 5.0);
+        let mut inside_apothem = 0;
+        for u in samples() {
+            let (x, y) = bokeh.sample_lens(u, 0.5, 0.5, 1.5, 1.0).unwrap();
+            let r = f64::sqrt(x * x + y * y);
+            assert!(r <= 1.0 + 1e-12);
+            inside_apothem += (r <= apothem) as u32;
+        }
+        // The pentagon covers more than its inscribed circle
+        assert!(inside_apothem < 1024);
+    }
+
+    #[test]
+    fn anamorphic_squeezes_horizontally() {
+        let bokeh = Bokeh { squeeze: 2.0, ..Bokeh::default() };
+        for u in samples() {
+            let (x, _) = bokeh.sample_lens(u, 0.5, 0.5, 1.5, 1.0).unwrap();
+            assert!(x.abs() <= 0.5);
+        }
+    }
+
+    #[test]
+    fn vignetting_only_clips_off_center() {
+        let bokeh = Bokeh { vignetting: 0.8, ..Bokeh::default() };
+        let passed = |s: f64, t: f64, lens_radius: f64| {
+            samples().filter(|&u| bokeh.sample_lens(u, s, t, 1.5, lens_radius).is_some()).count()
+        };
+
+        assert_eq!(passed(0.5, 0.5, 0.1), 1024);
+        assert!(passed(1.0, 1.0, 0.1) < 1024);
+        assert!(passed(1.0, 1.0, 0.1) < passed(0.7, 0.7, 0.1));
+        // Nothing to clip without an aperture
+        assert_eq!(passed(1.0, 1.0, 0.0), 1024);
+    }
+}
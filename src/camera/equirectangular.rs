@@ -0,0 +1,36 @@
+use std::f64::consts::PI;
+
+use crate::{vec3::{Vec3, Point3}, ray::Ray};
+
+use super::basis;
+
+// Latitude-longitude panorama around look_from: longitude across the image with
+// look_at in the middle, latitude from straight down at the bottom to straight up.
+// A non-zero eye offset renders one eye of an omnidirectional stereo panorama,
+// negative for the left eye.
+pub struct Equirectangular {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    eye_offset: f64
+}
+
+impl Equirectangular {
+    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
+        let longitude = (s - 0.5) * 2.0 * PI;
+        let latitude = (t - 0.5) * PI;
+
+        let horizontal = f64::sin(longitude)*self.u - f64::cos(longitude)*self.w;
+        let direction = f64::cos(latitude)*horizontal + f64::sin(latitude)*self.v;
+
+        // The eye sits to the side of the horizontal viewing direction
+        let side = f64::cos(longitude)*self.u + f64::sin(longitude)*self.w;
+        Ray::new(self.origin + self.eye_offset*side, direction)
+    }
+
+    pub fn new(look_from: Point3, look_at: Point3, vup: Vec3, eye_offset: f64) -> Equirectangular {
+        let (u, v, w) = basis(look_from, look_at, vup);
+        Equirectangular { origin: look_from, u, v, w, eye_offset }
+    }
+}
@@ -0,0 +1,56 @@
+use crate::{vec3::{Vec3, Point3}, ray::Ray, util::degrees_to_radians};
+
+use super::basis;
+
+// How the angle from the optical axis maps to the distance from the image center
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FisheyeMapping {
+    // Distance proportional to the angle
+    Equidistant,
+    // Equal solid angles get equal image areas
+    Equisolid
+}
+
+// Circular fisheye, the image circle touches the top and bottom of the image.
+// A pinhole, depth of field is not modelled.
+pub struct Fisheye {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    mapping: FisheyeMapping,
+    half_fov: f64,
+    aspect_ratio: f64
+}
+
+impl Fisheye {
+    pub fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        // Position in the image circle, radius 1 at its edge
+        let x = (2.0*s - 1.0) * self.aspect_ratio;
+        let y = 2.0*t - 1.0;
+        let r = f64::sqrt(x*x + y*y);
+        if r > 1.0 {
+            return None;
+        }
+
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * self.half_fov,
+            FisheyeMapping::Equisolid => 2.0 * f64::asin(r * f64::sin(self.half_fov/2.0))
+        };
+        let (cos_phi, sin_phi) = if r > 0.0 { (x/r, y/r) } else { (1.0, 0.0) };
+        let direction = f64::cos(theta)*(-self.w) + f64::sin(theta)*(cos_phi*self.u + sin_phi*self.v);
+
+        Some(Ray::new(self.origin, direction))
+    }
+
+    pub fn new(look_from: Point3, look_at: Point3, vup: Vec3, mapping: FisheyeMapping, fov: f64, aspect_ratio: f64) -> Fisheye {
+        let (u, v, w) = basis(look_from, look_at, vup);
+        Fisheye {
+            origin: look_from,
+            u, v, w,
+            mapping,
+            half_fov: degrees_to_radians(fov) / 2.0,
+            aspect_ratio
+        }
+    }
+}
@@ -0,0 +1,419 @@
+use crate::{
+    aabb::Aabb, hittable::{HitRecord, Hittable}, ray::Ray,
+    sampler::{PixelSampler, Sampler}, util::degrees_to_radians, vec3::{Point3, Vec3}
+};
+
+use self::{
+    aperture::Bokeh,
+    equirectangular::Equirectangular,
+    fisheye::{Fisheye, FisheyeMapping},
+    orthographic::Orthographic,
+    perspective::Perspective,
+    realistic::{Lens, Realistic},
+    stereo::{Stereo, StereoSettings}
+};
+
+pub mod aperture;
+pub mod perspective;
+pub mod physical;
+pub mod realistic;
+pub mod orthographic;
+pub mod fisheye;
+pub mod equirectangular;
+pub mod stereo;
+
+pub enum Camera {
+    Perspective(Perspective),
+    Orthographic(Orthographic),
+    Fisheye(Fisheye),
+    Equirectangular(Equirectangular),
+    Realistic(Realistic),
+    Stereo(Stereo)
+}
+
+impl Camera {
+    // Ray through the image at (s, t), both in [0, 1] with t = 0 at the bottom. None
+    // where the projection doesn't cover the image, like outside a circular fisheye.
+    pub fn get_ray(&self, s: f64, t: f64, sampler: &mut PixelSampler) -> Option<Ray> {
+        self.sample_ray(s, t, sampler).map(|(ray, _)| ray)
+    }
+
+    // The ray and the factor on the radiance it brings back, 1 except where a
+    // real lens lets less light reach the sensor
+    pub fn sample_ray(&self, s: f64, t: f64, sampler: &mut PixelSampler) -> Option<(Ray, f64)> {
+        match self {
+            Camera::Perspective(perspective) => perspective.get_ray(s, t, sampler).map(|ray| (ray, 1.0)),
+            Camera::Orthographic(orthographic) => orthographic.get_ray(s, t, sampler).map(|ray| (ray, 1.0)),
+            Camera::Fisheye(fisheye) => fisheye.get_ray(s, t).map(|ray| (ray, 1.0)),
+            Camera::Equirectangular(equirectangular) => Some((equirectangular.get_ray(s, t), 1.0)),
+            Camera::Realistic(realistic) => realistic.sample_ray(s, t, sampler),
+            Camera::Stereo(stereo) => stereo.sample_ray(s, t, sampler)
+        }
+    }
+}
+
+// How the camera maps directions onto the image
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    // Parallel rays, the view height is what the perspective camera sees at the focus distance
+    Orthographic,
+    // Circular fisheye covering `fov` degrees across the image height
+    Fisheye { mapping: FisheyeMapping, fov: f64 },
+    // Full 360 by 180 degree latitude-longitude panorama, best at a 2:1 aspect ratio
+    Equirectangular
+}
+
+impl Projection {
+    pub fn from_name(name: &str, fisheye_fov: f64) -> Option<Projection> {
+        match name {
+            "perspective" => Some(Projection::Perspective),
+            "orthographic" => Some(Projection::Orthographic),
+            "fisheye" => Some(Projection::Fisheye { mapping: FisheyeMapping::Equidistant, fov: fisheye_fov }),
+            "fisheye-equisolid" => Some(Projection::Fisheye { mapping: FisheyeMapping::Equisolid, fov: fisheye_fov }),
+            "equirectangular" => Some(Projection::Equirectangular),
+            _ => None
+        }
+    }
+
+    // Image position (s, t) of the raster point (px, py), row 0 at the top. The
+    // perspective camera keeps the book's mapping over width - 1 pixels; the
+    // others map the pixel edges onto the image edges, so the first and last
+    // columns of a panorama don't look the same way.
+    pub fn image_position(&self, px: f64, py: f64, width: u32, height: u32) -> (f64, f64) {
+        match self {
+            Projection::Perspective => (px / (width - 1) as f64, (height as f64 - py) / (height - 1) as f64),
+            _ => (px / width as f64, (height as f64 - py) / height as f64)
+        }
+    }
+}
+
+// What auto-focus focuses on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FocusTarget {
+    // Whatever is in front of the camera toward look_at
+    LookAt,
+    // Whatever is seen through the center of this pixel, row 0 at the top
+    Pixel(u32, u32)
+}
+
+// Where a camera is and how its lens is set, the values an animation can key
+#[derive(Debug, Clone)]
+pub struct CameraSettings {
+    pub projection: Projection,
+    pub look_from: Point3,
+    pub look_at: Point3,
+    pub vup: Vec3,
+    pub vfov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+    // Aperture shape and lens effects of the thin lens cameras
+    pub bokeh: Bokeh,
+    // Lens shift in image widths and heights, and tilt of the plane of focus
+    // in degrees about the horizontal and vertical axis, for the perspective camera
+    pub shift: (f64, f64),
+    pub tilt: (f64, f64),
+    // Trace through this lens instead of projecting
+    pub lens: Option<Lens>,
+    // Render both eyes of a stereo pair
+    pub stereo: Option<StereoSettings>
+}
+
+impl CameraSettings {
+    // Fails when a lens can't focus where it is asked to
+    pub fn build(&self, aspect_ratio: f64) -> Result<Camera, String> {
+        if let Some(stereo) = &self.stereo {
+            return Ok(Camera::Stereo(Stereo::new(self, stereo, aspect_ratio)?));
+        }
+        if let Some(lens) = &self.lens {
+            return Ok(Camera::Realistic(Realistic::new(self.look_from, self.look_at, self.vup, lens, self.focus_dist, aspect_ratio)?));
+        }
+        Ok(match self.projection {
+            Projection::Perspective => Camera::Perspective(Perspective::new(
+                self.look_from, self.look_at, self.vup, self.vfov, aspect_ratio, self.aperture, self.focus_dist
+            ).with_bokeh(self.bokeh.clone()).with_tilt_shift(self.shift, self.tilt)),
+            Projection::Orthographic => Camera::Orthographic(Orthographic::new(
+                self.look_from, self.look_at, self.vup, self.vfov, aspect_ratio, self.aperture, self.focus_dist
+            ).with_bokeh(self.bokeh.clone())),
+            Projection::Fisheye { mapping, fov } => Camera::Fisheye(Fisheye::new(
+                self.look_from, self.look_at, self.vup, mapping, fov, aspect_ratio
+            )),
+            Projection::Equirectangular => Camera::Equirectangular(Equirectangular::new(self.look_from, self.look_at, self.vup, 0.0))
+        })
+    }
+
+    // Distance from the camera to the first surface hit toward the target,
+    // measured along the view direction like focus_dist. Keeps the current
+    // distance when nothing is hit.
+    pub fn auto_focus(&self, world: &dyn Hittable, target: FocusTarget, width: u32, height: u32, aspect_ratio: f64) -> f64 {
+        let forward = (self.look_at - self.look_from).unit_vector();
+        let r = match target {
+            FocusTarget::LookAt => Ray::new(self.look_from, forward),
+            FocusTarget::Pixel(x, y) => {
+                // An ideal pinhole camera, so the ray leaves from the center of
+                // the lens without the lens elements bending it or a tilt
+                let pinhole = CameraSettings {
+                    aperture: 0.0, bokeh: Bokeh::default(), tilt: (0.0, 0.0), lens: None, stereo: None, ..self.clone()
+                };
+                let (s, t) = self.projection.image_position(x as f64 + 0.5, y as f64 + 0.5, width, height);
+                let mut sampler = PixelSampler::new(Sampler::Independent, 0, x, y, width, 0, 1);
+                match pinhole.build(aspect_ratio).ok().and_then(|cam| cam.get_ray(s, t, &mut sampler)) {
+                    Some(r) => r,
+                    None => return self.focus_dist
+                }
+            }
+        };
+
+        let mut rec = HitRecord::default();
+        if world.hit(&r, 0.001, f64::INFINITY, &mut rec) {
+            Vec3::dot(&(rec.p - self.look_from), &forward)
+        } else {
+            self.focus_dist
+        }
+    }
+
+    // Aims at the center of the box and backs away along the view direction
+    // until its bounding sphere fits the narrower field of view, focused on the center
+    pub fn frame(&mut self, bounds: &Aabb, aspect_ratio: f64) {
+        let center = 0.5 * (bounds.minimum + bounds.maximum);
+        let radius = 0.5 * (bounds.maximum - bounds.minimum).length();
+        let half_vfov = degrees_to_radians(self.vfov) / 2.0;
+        let half_hfov = f64::atan(aspect_ratio * f64::tan(half_vfov));
+        let distance = radius / f64::sin(f64::min(half_vfov, half_hfov));
+
+        let backwards = (self.look_from - self.look_at).unit_vector();
+        self.look_at = center;
+        self.look_from = center + distance * backwards;
+        self.focus_dist = distance;
+    }
+}
+
+// Right, up and backwards unit vectors of a camera at look_from looking at look_at
+fn basis(look_from: Point3, look_at: Point3, vup: Vec3) -> (Vec3, Vec3, Vec3) {
+    let w = (look_from - look_at).unit_vector();
+    let u = Vec3::cross(&vup, &w).unit_vector();
+    let v = Vec3::cross(&w, &u);
+    (u, v, w)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{CameraSettings, FocusTarget, Projection, aperture::Bokeh, fisheye::FisheyeMapping, stereo::{StereoLayout, StereoSettings}};
+    use crate::{
+        aabb::Aabb, hittable_list::HittableList, material::{Material, lambertian::Lambertian}, ray::Ray,
+        sampler::{PixelSampler, Sampler}, sphere::Sphere, vec3::{Color, Point3, Vec3}
+    };
+
+    fn camera(projection: Projection) -> CameraSettings {
+        CameraSettings {
+            projection,
+            look_from: Point3::new(1.0, 2.0, 3.0),
+            look_at: Point3::new(1.0, 2.0, 0.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vfov: 90.0,
+            aperture: 0.0,
+            focus_dist: 3.0,
+            bokeh: Bokeh::default(),
+            shift: (0.0, 0.0),
+            tilt: (0.0, 0.0),
+            lens: None,
+            stereo: None
+        }
+    }
+
+    fn direction(settings: &CameraSettings, aspect_ratio: f64, s: f64, t: f64) -> Option<Vec3> {
+        let mut sampler = PixelSampler::new(Sampler::Independent, 0, 0, 0, 1, 0, 1);
+        settings.build(aspect_ratio).unwrap().get_ray(s, t, &mut sampler).map(|r| r.direction().unit_vector())
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn every_projection_looks_at_the_target_in_the_center() {
+        let projections = [
+            Projection::Perspective,
+            Projection::Orthographic,
+            Projection::Fisheye { mapping: FisheyeMapping::Equidistant, fov: 180.0 },
+            Projection::Fisheye { mapping: FisheyeMapping::Equisolid, fov: 180.0 },
+            Projection::Equirectangular
+        ];
+        for projection in projections {
+            let forward = direction(&camera(projection), 2.0, 0.5, 0.5).unwrap();
+            assert_close(forward, Vec3::new(0.0, 0.0, -1.0));
+        }
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let settings = camera(Projection::Orthographic);
+        let mut sampler = PixelSampler::new(Sampler::Independent, 0, 0, 0, 1, 0, 1);
+        let cam = settings.build(1.0).unwrap();
+        let corner = cam.get_ray(0.0, 0.0, &mut sampler).unwrap();
+
+        assert_close(corner.direction().unit_vector(), Vec3::new(0.0, 0.0, -1.0));
+        // 90 degrees at a focus distance of 3 is 6 units across
+        assert_close(corner.origin(), Point3::new(-2.0, -1.0, 3.0));
+    }
+
+    #[test]
+    fn fisheye_edge_is_half_its_field_of_view() {
+        for mapping in [FisheyeMapping::Equidistant, FisheyeMapping::Equisolid] {
+            let settings = camera(Projection::Fisheye { mapping, fov: 180.0 });
+
+            assert_close(direction(&settings, 1.0, 0.5, 1.0).unwrap(), Vec3::new(0.0, 1.0, 0.0));
+            assert_close(direction(&settings, 1.0, 0.0, 0.5).unwrap(), Vec3::new(-1.0, 0.0, 0.0));
+            assert!(direction(&settings, 1.0, 0.0, 0.0).is_none());
+        }
+    }
+
+    #[test]
+    fn equirectangular_covers_the_sphere() {
+        let settings = camera(Projection::Equirectangular);
+
+        assert_close(direction(&settings, 2.0, 0.0, 0.5).unwrap(), Vec3::new(0.0, 0.0, 1.0));
+        assert_close(direction(&settings, 2.0, 0.75, 0.5).unwrap(), Vec3::new(1.0, 0.0, 0.0));
+        assert_close(direction(&settings, 2.0, 0.3, 1.0).unwrap(), Vec3::new(0.0, 1.0, 0.0));
+        assert_close(direction(&settings, 2.0, 0.6, 0.0).unwrap(), Vec3::new(0.0, -1.0, 0.0));
+    }
+
+    #[test]
+    fn equirectangular_columns_do_not_wrap_onto_each_other() {
+        let settings = camera(Projection::Equirectangular);
+        let (width, height) = (64, 32);
+        let longitude = |x: u32| {
+            let (s, t) = settings.projection.image_position(x as f64 + 0.5, 16.0, width, height);
+            let d = direction(&settings, 2.0, s, t).unwrap();
+            f64::atan2(d.x, -d.z).to_degrees()
+        };
+
+        let apart = longitude(width - 1) - longitude(0);
+        assert!((apart - 360.0 * (1.0 - 1.0 / width as f64)).abs() < 1e-9, "{}", apart);
+    }
+
+    #[test]
+    fn stereo_eyes_converge() {
+        let stereo = StereoSettings { layout: StereoLayout::SideBySide, interocular: 0.2, convergence: Some(5.0) };
+        let settings = CameraSettings { stereo: Some(stereo), ..camera(Projection::Perspective) };
+        let cam = settings.build(2.0).unwrap();
+        let mut sampler = PixelSampler::new(Sampler::Independent, 0, 0, 0, 1, 0, 1);
+
+        // Centers of the left and right halves
+        let left = cam.get_ray(0.25, 0.5, &mut sampler).unwrap();
+        let right = cam.get_ray(0.75, 0.5, &mut sampler).unwrap();
+        assert_close(left.origin(), Point3::new(0.9, 2.0, 3.0));
+        assert_close(right.origin(), Point3::new(1.1, 2.0, 3.0));
+
+        let at_convergence = |r: &Ray| r.at((-2.0 - r.origin().z) / r.direction().z);
+        assert_close(at_convergence(&left), Point3::new(1.0, 2.0, -2.0));
+        assert_close(at_convergence(&right), Point3::new(1.0, 2.0, -2.0));
+    }
+
+    #[test]
+    fn ods_eyes_sit_beside_every_direction() {
+        let stereo = StereoSettings { layout: StereoLayout::TopBottom, interocular: 0.2, convergence: None };
+        let settings = CameraSettings { stereo: Some(stereo), ..camera(Projection::Equirectangular) };
+        let cam = settings.build(1.0).unwrap();
+        let mut sampler = PixelSampler::new(Sampler::Independent, 0, 0, 0, 1, 0, 1);
+
+        for s in [0.1, 0.4, 0.5, 0.8] {
+            let left = cam.get_ray(s, 0.75, &mut sampler).unwrap();
+            let right = cam.get_ray(s, 0.25, &mut sampler).unwrap();
+            let baseline = right.origin() - left.origin();
+
+            assert!((baseline.length() - 0.2).abs() < 1e-9);
+            assert!(Vec3::dot(&baseline, &left.direction()).abs() < 1e-9);
+            // The right eye is to the right of the viewing direction
+            assert!(Vec3::cross(&left.direction(), &baseline).y < 0.0);
+        }
+    }
+
+    #[test]
+    fn shift_keeps_verticals_parallel() {
+        let settings = CameraSettings { shift: (0.0, 0.5), ..camera(Projection::Perspective) };
+
+        // The view moves up without the camera turning: the old top edge is now the center
+        assert_close(direction(&settings, 1.0, 0.5, 0.5).unwrap(), Vec3::new(0.0, 1.0, -1.0).unit_vector());
+        assert_close(direction(&settings, 1.0, 0.5, 0.0).unwrap(), Vec3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn tilted_focus_plane() {
+        // Plane of focus turned 45 degrees, its top leaning away
+        let settings = CameraSettings { aperture: 1.0, tilt: (45.0, 0.0), ..camera(Projection::Perspective) };
+        let cam = settings.build(1.0).unwrap();
+
+        for t in [0.2, 0.5, 0.7] {
+            let mut focus = None;
+            for index in 0..8 {
+                let mut sampler = PixelSampler::new(Sampler::Independent, 0, 0, 0, 1, index, 8);
+                let r = cam.get_ray(0.5, t, &mut sampler).unwrap();
+                // Rays of a pixel meet on the plane y + z = 2, through the center of focus (1, 2, 0)
+                let (o, d) = (r.origin(), r.direction());
+                let p = r.at((2.0 - o.y - o.z) / (d.y + d.z));
+                if let Some(focus) = focus {
+                    assert_close(p, focus);
+                }
+                focus = Some(p);
+            }
+        }
+    }
+
+    #[test]
+    fn tilted_focus_plane_beyond_the_horizon() {
+        // The top of the 90 degree view looks past where the plane of focus vanishes
+        let settings = CameraSettings { aperture: 1.0, tilt: (60.0, 0.0), ..camera(Projection::Perspective) };
+        let cam = settings.build(1.0).unwrap();
+
+        for t in [0.0, 0.5, 0.8, 0.9, 1.0] {
+            let rays: Vec<Ray> = (0..8).map(|index| {
+                let mut sampler = PixelSampler::new(Sampler::Independent, 0, 0, 0, 1, index, 8);
+                cam.get_ray(0.5, t, &mut sampler).unwrap()
+            }).collect();
+            for r in &rays {
+                let d = r.direction();
+                assert!(d.length().is_finite() && d.z < 0.0, "{:?} at {}", d, t);
+            }
+            // Above t = (1 + 1/tan 60) / 2, about 0.79, the line of sight runs away from
+            // the plane and the pixel is focused at infinity
+            if t >= 0.9 {
+                assert_close(rays[0].direction().unit_vector(), rays[1].direction().unit_vector());
+            }
+        }
+    }
+
+    #[test]
+    fn auto_focus_on_the_first_hit() {
+        let settings = camera(Projection::Perspective);
+        let material = Arc::new(Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(Point3::new(1.0, 2.0, -2.0), 1.0, &material)));
+
+        // The sphere's front is 4 units in front of the camera
+        assert!((settings.auto_focus(&world, FocusTarget::LookAt, 101, 101, 1.0) - 4.0).abs() < 1e-9);
+        assert!((settings.auto_focus(&world, FocusTarget::Pixel(50, 50), 101, 101, 1.0) - 4.0).abs() < 1e-2);
+        // A corner pixel sees nothing and keeps the focus distance
+        assert_eq!(settings.auto_focus(&world, FocusTarget::Pixel(0, 0), 101, 101, 1.0), 3.0);
+
+        // Measured along the center ray, whatever the lens does
+        let tilted = CameraSettings { aperture: 0.5, tilt: (15.0, 0.0), ..settings };
+        assert!((tilted.auto_focus(&world, FocusTarget::Pixel(50, 50), 101, 101, 1.0) - 4.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn frame_fits_the_bounding_sphere() {
+        let mut settings = camera(Projection::Perspective);
+        settings.frame(&Aabb::new(Point3::new(4.0, -1.0, -1.0), Point3::new(6.0, 1.0, 1.0)), 2.0);
+
+        // A radius of sqrt(3) fits the 90 degree vertical field of view at sqrt(6),
+        // seen from the same direction as before
+        let distance = f64::sqrt(6.0);
+        assert_close(settings.look_at, Point3::new(5.0, 0.0, 0.0));
+        assert_close(settings.look_from, Point3::new(5.0, 0.0, distance));
+        assert!((settings.focus_dist - distance).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,60 @@
+use crate::{vec3::{Vec3, Point3}, ray::Ray, util::degrees_to_radians, sampler::PixelSampler};
+
+use super::{aperture::Bokeh, basis};
+
+// Parallel projection onto the plane through look_from. With an aperture the
+// rays converge on the focus plane like for the thin lens.
+pub struct Orthographic {
+    lower_left_corner: Point3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    focus_dist: f64,
+    lens_radius: f64,
+    aspect_ratio: f64,
+    bokeh: Bokeh
+}
+
+impl Orthographic {
+    pub fn get_ray(&self, s: f64, t: f64, sampler: &mut PixelSampler) -> Option<Ray> {
+        let origin = self.lower_left_corner + s*self.horizontal + t*self.vertical;
+        let (x, y) = self.bokeh.sample_lens(sampler.get_2d(), s, t, self.aspect_ratio, self.lens_radius)?;
+        let offset = self.lens_radius * (self.u*x + self.v*y);
+        Some(Ray::new(origin + offset, -self.focus_dist*self.w - offset))
+    }
+
+    pub fn new(
+        look_from: Point3,
+        look_at: Point3,
+        vup: Vec3,
+        vfov: f64,
+        aspect_ratio: f64,
+        aperture: f64,
+        focus_dist: f64
+    ) -> Orthographic {
+        // The view the perspective camera would have at the focus distance
+        let viewport_height = 2.0 * focus_dist * f64::tan(degrees_to_radians(vfov)/2.0);
+        let viewport_width = aspect_ratio * viewport_height;
+
+        let (u, v, w) = basis(look_from, look_at, vup);
+        let horizontal = viewport_width * u;
+        let vertical = viewport_height * v;
+
+        Orthographic {
+            lower_left_corner: look_from - horizontal/2.0 - vertical/2.0,
+            horizontal,
+            vertical,
+            u, v, w,
+            focus_dist,
+            lens_radius: aperture / 2.0,
+            aspect_ratio,
+            bokeh: Bokeh::default()
+        }
+    }
+
+    pub fn with_bokeh(self, bokeh: Bokeh) -> Orthographic {
+        Orthographic { bokeh, ..self }
+    }
+}
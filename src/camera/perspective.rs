@@ -0,0 +1,102 @@
+use crate::{vec3::{Vec3, Point3}, ray::Ray, util::degrees_to_radians, sampler::PixelSampler};
+
+use super::aperture::Bokeh;
+
+// Thin lens camera: pinhole projection with depth of field from the lens aperture.
+// Like a view camera, the lens can be shifted parallel to the sensor and the
+// plane of focus tilted (Scheimpflug) instead of facing the camera.
+pub struct Perspective {
+    origin: Point3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    lower_left_corner: Vec3,
+    u: Vec3,
+    v: Vec3, 
+    w: Vec3,
+    lens_radius: f64,
+    aspect_ratio: f64,
+    bokeh: Bokeh,
+    // Shift of the image window, in image widths and heights
+    shift: (f64, f64),
+    // Normal of the tilted plane of focus, which goes through the center of the untilted one
+    focus_normal: Option<Vec3>,
+    focus_dist: f64
+}
+
+impl Perspective {
+    pub fn get_ray(&self, s: f64, t: f64, sampler: &mut PixelSampler) -> Option<Ray> {
+        // Vignetting is centered on the optical axis, not on the shifted image
+        let (x, y) = self.bokeh.sample_lens(sampler.get_2d(), s + self.shift.0, t + self.shift.1, self.aspect_ratio, self.lens_radius)?;
+        let offset = self.lens_radius * (self.u*x + self.v*y);
+
+        let mut target = self.lower_left_corner + s*self.horizontal + t*self.vertical;
+        if let Some(normal) = self.focus_normal {
+            // Where the line of sight through the lens center meets the tilted plane
+            let direction = target - self.origin;
+            let facing = Vec3::dot(&direction, &normal);
+            if facing >= 0.0 {
+                // It never does in front of the camera: focus at infinity
+                return Some(Ray::new(self.origin + offset, direction));
+            }
+            let distance = -self.focus_dist * Vec3::dot(&self.w, &normal) / facing;
+            target = self.origin + distance*direction;
+        }
+        Some(Ray::new(self.origin + offset, target - self.origin - offset))
+    }
+
+    pub fn new(
+        look_from: Point3,
+        look_at: Point3,
+        vup: Vec3,
+        vfov: f64,
+        aspect_ratio: f64,
+        aperture: f64,
+        focus_dist: f64
+    ) -> Perspective {
+        let theta = degrees_to_radians(vfov);
+        let h = f64::tan(theta/2.0);
+        let viewport_height = 2.0 * h;
+        let viewport_width = aspect_ratio * viewport_height;
+
+        let w = (look_from - look_at).unit_vector();
+        let u = (Vec3::cross(&vup, &w)).unit_vector();
+        let v = Vec3::cross(&w, &u);
+
+        let origin = look_from;
+        let horizontal = focus_dist * viewport_width * u;
+        let vertical = focus_dist * viewport_height * v;
+        let lower_left_corner = origin - horizontal/2.0 - vertical/2.0 - focus_dist*w;
+        let lens_radius = aperture / 2.0;
+        
+        Perspective {
+            origin,
+            horizontal,
+            vertical,
+            lower_left_corner,
+            u, v, w,
+            lens_radius,
+            aspect_ratio,
+            bokeh: Bokeh::default(),
+            shift: (0.0, 0.0),
+            focus_normal: None,
+            focus_dist
+        }
+    }
+
+    // Shifts the image window by `shift` image widths and heights, moving the
+    // view without turning the camera. `tilt` turns the plane of focus by
+    // degrees about the horizontal and the vertical axis, positive angles move
+    // its top and its right side away from the camera.
+    pub fn with_tilt_shift(self, shift: (f64, f64), tilt: (f64, f64)) -> Perspective {
+        let lower_left_corner = self.lower_left_corner + shift.0*self.horizontal + shift.1*self.vertical;
+        let focus_normal = (tilt != (0.0, 0.0)).then(|| {
+            let (tilt, swing) = (degrees_to_radians(tilt.0), degrees_to_radians(tilt.1));
+            (self.w + f64::tan(tilt)*self.v + f64::tan(swing)*self.u).unit_vector()
+        });
+        Perspective { lower_left_corner, shift, focus_normal, ..self }
+    }
+
+    pub fn with_bokeh(self, bokeh: Bokeh) -> Perspective {
+        Perspective { bokeh, ..self }
+    }
+}
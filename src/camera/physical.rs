@@ -0,0 +1,88 @@
+// Lens and exposure settings as on a real camera, from which the field of
+// view, lens radius and image brightness follow. Lengths of the lens and
+// sensor are in millimeters, the scene in meters times units_per_meter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhysicalCamera {
+    pub focal_length: f64,
+    pub sensor_width: f64,
+    pub sensor_height: f64,
+    pub f_stop: f64,
+    // Seconds
+    pub shutter: f64,
+    pub iso: f64,
+    pub units_per_meter: f64
+}
+
+impl Default for PhysicalCamera {
+    fn default() -> Self {
+        // A 50mm lens on a full frame sensor
+        PhysicalCamera {
+            focal_length: 50.0,
+            sensor_width: 36.0,
+            sensor_height: 24.0,
+            f_stop: 8.0,
+            shutter: 1.0 / 125.0,
+            iso: 100.0,
+            units_per_meter: 1.0
+        }
+    }
+}
+
+impl PhysicalCamera {
+    fn focal_length_units(&self) -> f64 {
+        self.focal_length / 1000.0 * self.units_per_meter
+    }
+
+    // Vertical field of view in degrees of an image with `aspect_ratio`, as large
+    // as fits on the sensor. Focusing closer moves the lens away from the sensor,
+    // which narrows the view (focus breathing).
+    pub fn vfov(&self, aspect_ratio: f64, focus_dist: f64) -> f64 {
+        let f = self.focal_length_units();
+        let image_distance = if focus_dist > f { f * focus_dist / (focus_dist - f) } else { f };
+        let height = f64::min(self.sensor_height, self.sensor_width / aspect_ratio) / 1000.0 * self.units_per_meter;
+        2.0 * f64::atan(height / (2.0 * image_distance)).to_degrees()
+    }
+
+    // Diameter of the entrance pupil in scene units
+    pub fn aperture(&self) -> f64 {
+        self.focal_length_units() / self.f_stop
+    }
+
+    // Factor on the scene's radiance. Radiance 1 is exposed as white at EV 15
+    // (the "sunny 16" f/16, 1/125 s, ISO 100), and every stop more light doubles it.
+    pub fn exposure(&self) -> f64 {
+        let ev100 = f64::log2(self.f_stop * self.f_stop / self.shutter * 100.0 / self.iso);
+        f64::powf(2.0, 15.0 - ev100)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PhysicalCamera;
+
+    #[test]
+    fn fifty_millimeter_full_frame() {
+        let camera = PhysicalCamera::default();
+
+        // 2 atan(12 / 50), focused far away
+        assert!((camera.vfov(1.5, 1e9) - 26.991).abs() < 1e-3);
+        // Focusing close narrows the view
+        assert!(camera.vfov(1.5, 0.5) < camera.vfov(1.5, 10.0));
+        // A wider image uses the full sensor width
+        assert!((camera.vfov(3.0, 1e9) - 2.0 * f64::atan(6.0 / 50.0).to_degrees()).abs() < 1e-3);
+        assert!((camera.aperture() - 0.00625).abs() < 1e-12);
+    }
+
+    #[test]
+    fn exposure_doubles_per_stop() {
+        let sunny = PhysicalCamera { f_stop: 16.0, shutter: 1.0 / 128.0, iso: 100.0, ..PhysicalCamera::default() };
+        assert!((sunny.exposure() - 1.0).abs() < 1e-12);
+
+        let slower = PhysicalCamera { shutter: 1.0 / 64.0, ..sunny };
+        let wider = PhysicalCamera { f_stop: 16.0 / f64::sqrt(2.0), ..sunny };
+        let faster_film = PhysicalCamera { iso: 200.0, ..sunny };
+        for camera in [slower, wider, faster_film] {
+            assert!((camera.exposure() - 2.0).abs() < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,490 @@
+use std::{fs, sync::Arc};
+
+use crate::{ray::Ray, sampler::PixelSampler, vec3::{Point3, Vec3}};
+
+use super::basis;
+
+// One refracting surface of a lens prescription, or its aperture stop. Lengths in millimeters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LensElement {
+    // Radius of curvature, positive when the center lies towards the sensor, 0 for the flat stop
+    pub radius: f64,
+    // Distance to the next surface towards the sensor
+    pub thickness: f64,
+    // Index of refraction of the glass behind the surface, 0 or 1 for air
+    pub ior: f64,
+    // Diameter of the clear aperture
+    pub aperture: f64
+}
+
+// A lens prescription and the sensor behind it
+#[derive(Debug, Clone)]
+pub struct Lens {
+    pub elements: Arc<Vec<LensElement>>,
+    pub sensor_width: f64,
+    pub sensor_height: f64,
+    pub units_per_meter: f64
+}
+
+impl Lens {
+    // Reads a prescription with one surface per line, from the front of the lens:
+    //
+    //     <radius> <thickness> <ior> <aperture diameter>
+    //
+    // The thickness of the last surface is the distance to the sensor before
+    // focusing. Text after '#' is a comment.
+    pub fn load_elements(path: &str) -> Result<Vec<LensElement>, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("cannot read lens {}: {}", path, err))?;
+        Lens::parse_elements(&text).map_err(|err| format!("{}: {}", path, err))
+    }
+
+    pub fn parse_elements(text: &str) -> Result<Vec<LensElement>, String> {
+        let mut elements = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            if line.trim().is_empty() {
+                continue;
+            }
+            let values: Vec<f64> = line.split_whitespace().map(|v| v.parse().map_err(|_| format!("line {}: invalid number {}", number + 1, v)))
+                .collect::<Result<_, _>>()?;
+            match values[..] {
+                [radius, thickness, ior, aperture] if aperture > 0.0 && thickness >= 0.0 && ior >= 0.0 => {
+                    elements.push(LensElement { radius, thickness, ior, aperture });
+                }
+                _ => return Err(format!("line {}: expected radius, thickness, ior and a positive aperture", number + 1))
+            }
+        }
+        if elements.is_empty() {
+            return Err(String::from("the lens has no surfaces"));
+        }
+        Ok(elements)
+    }
+
+    // Focal length over the diameter of the entrance pupil, None if the lens
+    // doesn't focus parallel light
+    pub fn f_stop(&self) -> Option<f64> {
+        let surfaces = layout(&self.elements, self.elements[self.elements.len() - 1].thickness);
+        Some(focal_length(&surfaces)? / (2.0 * entrance_pupil_radius(&surfaces)))
+    }
+
+    // Narrows the aperture stop until the lens works at `f_stop`, its focal
+    // length over the diameter of its entrance pupil
+    pub fn stop_down(&mut self, f_stop: f64) -> Result<(), String> {
+        let stop = self.elements.iter().position(|element| element.radius == 0.0)
+            .ok_or("the lens has no aperture stop (a surface with radius 0)")?;
+        let surfaces = layout(&self.elements, self.elements[self.elements.len() - 1].thickness);
+        let focal_length = focal_length(&surfaces).ok_or("the lens doesn't bring parallel light to a focus")?;
+        let wanted = focal_length / (2.0 * f_stop);
+        let widest = entrance_pupil_radius(&surfaces);
+        if wanted > widest * (1.0 + 1e-6) {
+            return Err(format!("the lens opens only to f/{:.1}", focal_length / (2.0 * widest)));
+        }
+
+        // The pupil grows with the stop, search for the stop that gives the wanted one
+        let mut elements = self.elements.as_ref().clone();
+        let (mut low, mut high) = (0.0, elements[stop].aperture);
+        for _ in 0..40 {
+            elements[stop].aperture = 0.5 * (low + high);
+            if entrance_pupil_radius(&layout(&elements, elements[elements.len() - 1].thickness)) < wanted {
+                low = elements[stop].aperture;
+            } else {
+                high = elements[stop].aperture;
+            }
+        }
+        elements[stop].aperture = high;
+        self.elements = Arc::new(elements);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Surface {
+    element: LensElement,
+    // Position of the vertex on the optical axis, the sensor is at 0 and the scene towards +z
+    z: f64
+}
+
+// Box on the rear element that the rays from a ring of sensor points can get through
+type PupilBounds = Option<(f64, f64, f64, f64)>;
+
+const PUPIL_BINS: usize = 64;
+
+// Traces rays from the sensor through every surface of a real lens, which
+// gives its distortion, vignetting, field curvature and focus breathing.
+// Focusing moves the lens away from the sensor. Rays are aimed at the part of
+// the rear element that the sensor point can see through the whole lens (the
+// exit pupil), and rays that still hit a lens barrel are returned as None.
+// Each ray is weighted by the irradiance it brings to the sensor, cos^4 times
+// the pupil area over the squared distance to it, so the image darkens toward
+// the corners like a photograph does.
+pub struct Realistic {
+    surfaces: Vec<Surface>,
+    film_width: f64,
+    film_height: f64,
+    pupils: Vec<PupilBounds>,
+    // Inverse of the mean ray weight at the center of the image, which keeps its exposure at 1
+    normalization: f64,
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    // Scene units per millimeter
+    scale: f64
+}
+
+impl Realistic {
+    // Ray through the image at (s, t) and its weight
+    pub fn sample_ray(&self, s: f64, t: f64, sampler: &mut PixelSampler) -> Option<(Ray, f64)> {
+        // The lens turns the image upside down
+        let x = -(s - 0.5) * self.film_width;
+        let y = -(t - 0.5) * self.film_height;
+        let r = f64::sqrt(x*x + y*y);
+
+        let bin = usize::min((r / self.half_diagonal() * PUPIL_BINS as f64) as usize, PUPIL_BINS - 1);
+        let bounds = self.pupils[bin]?;
+        let film = Vec3::new(x, y, 0.0);
+        let rear = self.pupil_point(bounds, film, sampler.get_2d());
+        let (o, d) = trace(&self.surfaces, film, rear - film, true)?;
+
+        // Lens space to the world, with the front of the lens at the camera position
+        let front = self.surfaces[0].z;
+        let ray = Ray::new(
+            self.origin + self.scale*(o.x*self.u + o.y*self.v) - self.scale*(o.z - front)*self.w,
+            d.x*self.u + d.y*self.v - d.z*self.w
+        );
+        Some((ray, self.normalization * self.irradiance_weight(bounds, film, rear)))
+    }
+
+    // Point on the rear element within the pupil bounds of a sensor point
+    fn pupil_point(&self, (x0, x1, y0, y1): (f64, f64, f64, f64), film: Vec3, (a, b): (f64, f64)) -> Vec3 {
+        let (px, py) = (x0 + a*(x1 - x0), y0 + b*(y1 - y0));
+
+        // The bounds are for a sensor point on the x axis, rotate them to this one
+        let r = f64::hypot(film.x, film.y);
+        let (cos, sin) = if r > 0.0 { (film.x/r, film.y/r) } else { (1.0, 0.0) };
+        Vec3::new(px*cos - py*sin, px*sin + py*cos, self.rear().z)
+    }
+
+    // Irradiance at the sensor point from a ray toward `rear`, sampled uniformly
+    // over the pupil bounds: cos^4 of its angle times the area over the distance squared
+    fn irradiance_weight(&self, (x0, x1, y0, y1): (f64, f64, f64, f64), film: Vec3, rear: Vec3) -> f64 {
+        let cos = (rear - film).unit_vector().z;
+        let distance = self.rear().z;
+        cos.powi(4) * (x1 - x0) * (y1 - y0) / (distance * distance)
+    }
+
+    // Mean weight of the rays through the center of the sensor, blocked ones counting as 0
+    fn center_weight(&self) -> f64 {
+        const GRID: usize = 64;
+        let Some(bounds) = self.pupils[0] else {
+            return 0.0;
+        };
+        let film = Vec3::new(0.0, 0.0, 0.0);
+        let mut total = 0.0;
+        for i in 0..GRID {
+            for j in 0..GRID {
+                let u = ((i as f64 + 0.5) / GRID as f64, (j as f64 + 0.5) / GRID as f64);
+                let rear = self.pupil_point(bounds, film, u);
+                if trace(&self.surfaces, film, rear - film, true).is_some() {
+                    total += self.irradiance_weight(bounds, film, rear);
+                }
+            }
+        }
+        total / (GRID * GRID) as f64
+    }
+
+    pub fn new(look_from: Point3, look_at: Point3, vup: Vec3, lens: &Lens, focus_dist: f64, aspect_ratio: f64) -> Result<Realistic, String> {
+        let (u, v, w) = basis(look_from, look_at, vup);
+        let film_height = f64::min(lens.sensor_height, lens.sensor_width / aspect_ratio);
+        let scale = lens.units_per_meter / 1000.0;
+
+        let mut camera = Realistic {
+            surfaces: layout(&lens.elements, lens.elements[lens.elements.len() - 1].thickness),
+            film_width: film_height * aspect_ratio,
+            film_height,
+            pupils: Vec::new(),
+            normalization: 1.0,
+            origin: look_from,
+            u, v, w,
+            scale
+        };
+        let film_distance = camera.focus(focus_dist / scale)
+            .ok_or(format!("the lens cannot focus at a distance of {}", focus_dist))?;
+        camera.surfaces = layout(&lens.elements, film_distance);
+        camera.pupils = (0..PUPIL_BINS).map(|bin| camera.pupil_bounds(bin)).collect();
+        let center = camera.center_weight();
+        if center > 0.0 {
+            camera.normalization = 1.0 / center;
+        }
+        Ok(camera)
+    }
+
+    fn rear(&self) -> &Surface {
+        &self.surfaces[self.surfaces.len() - 1]
+    }
+
+    fn half_diagonal(&self) -> f64 {
+        f64::sqrt(self.film_width*self.film_width + self.film_height*self.film_height) / 2.0
+    }
+
+    // Distance between the rear element and the sensor that brings the plane
+    // `distance` in front of the lens into focus, found by tracing a ray from
+    // the point on the axis there and seeing where it crosses the axis again
+    fn focus(&self, distance: f64) -> Option<f64> {
+        let front = &self.surfaces[0];
+        let height = 0.05 * front.element.aperture;
+        let from = Vec3::new(0.0, 0.0, front.z + distance);
+        let (o, d) = trace(&self.surfaces, from, Vec3::new(height, 0.0, front.z) - from, false)?;
+        if o.x * d.x >= 0.0 {
+            // Diverging, nothing behind the lens is in focus
+            return None;
+        }
+        let crossing = o.z - o.x / d.x * d.z;
+        let film_distance = self.rear().z - crossing;
+        (film_distance > 0.0).then_some(film_distance)
+    }
+
+    // Bounds of the rear element points reachable from sensor points at the
+    // middle of ring `bin`, found by tracing a grid of rays over the rear element
+    fn pupil_bounds(&self, bin: usize) -> PupilBounds {
+        const GRID: usize = 32;
+        let radius = self.rear().element.aperture / 2.0;
+        let cell = 2.0 * radius / GRID as f64;
+        let film = Vec3::new((bin as f64 + 0.5) / PUPIL_BINS as f64 * self.half_diagonal(), 0.0, 0.0);
+
+        let mut bounds: PupilBounds = None;
+        for i in 0..GRID {
+            for j in 0..GRID {
+                let (x, y) = (-radius + (i as f64 + 0.5) * cell, -radius + (j as f64 + 0.5) * cell);
+                let rear = Vec3::new(x, y, self.rear().z);
+                if trace(&self.surfaces, film, rear - film, true).is_some() {
+                    bounds = Some(match bounds {
+                        Some((x0, x1, y0, y1)) => (x0.min(x), x1.max(x), y0.min(y), y1.max(y)),
+                        None => (x, x, y, y)
+                    });
+                }
+            }
+        }
+        // Grow by a cell so rays between the grid points aren't missed
+        bounds.map(|(x0, x1, y0, y1)| (x0 - cell, x1 + cell, y0 - cell, y1 + cell))
+    }
+}
+
+// Traces a ray in lens space through all surfaces: back to front when it
+// starts at the sensor, front to back when it comes from the scene. None if
+// an aperture blocks it or it is totally internally reflected.
+fn trace(surfaces: &[Surface], mut origin: Vec3, mut direction: Vec3, from_film: bool) -> Option<(Vec3, Vec3)> {
+    let n = surfaces.len();
+    for k in 0..n {
+        let i = if from_film { n - 1 - k } else { k };
+        let Surface { element, z } = surfaces[i];
+
+        let (t, normal) = if element.radius == 0.0 {
+            ((z - origin.z) / direction.z, Vec3::new(0.0, 0.0, 1.0))
+        }
+        else {
+            let center = Vec3::new(0.0, 0.0, z - element.radius);
+            let oc = origin - center;
+            let a = direction.length_squared();
+            let half_b = Vec3::dot(&oc, &direction);
+            let c = oc.length_squared() - element.radius*element.radius;
+            let discriminant = half_b*half_b - a*c;
+            if discriminant < 0.0 {
+                return None;
+            }
+            // The vertex is the sphere's far side for a ray coming from the
+            // center's side, and its near side otherwise
+            let near = (direction.z > 0.0) != (element.radius > 0.0);
+            let sqrtd = discriminant.sqrt();
+            let t = if near { (-half_b - sqrtd) / a } else { (-half_b + sqrtd) / a };
+            (t, (origin + t*direction - center) / element.radius.abs())
+        };
+        if t.is_nan() || t <= 0.0 {
+            return None;
+        }
+
+        origin += t*direction;
+        let half_aperture = element.aperture / 2.0;
+        if origin.x*origin.x + origin.y*origin.y > half_aperture*half_aperture {
+            return None;
+        }
+
+        if element.radius != 0.0 {
+            let behind = medium(element.ior);
+            let in_front = if i > 0 { medium(surfaces[i - 1].element.ior) } else { 1.0 };
+            let eta = if from_film { behind / in_front } else { in_front / behind };
+            let normal = if Vec3::dot(&normal, &direction) > 0.0 { -normal } else { normal };
+            direction = refract(&direction.unit_vector(), &normal, eta)?;
+        }
+    }
+    Some((origin, direction))
+}
+
+// Ray coming in parallel to the axis at `height`, as it leaves the back of the lens
+fn parallel_ray(surfaces: &[Surface], height: f64) -> Option<(Vec3, Vec3)> {
+    trace(surfaces, Vec3::new(height, 0.0, surfaces[0].z + 1.0), Vec3::new(0.0, 0.0, -1.0), false)
+}
+
+// Effective focal length, from the angle a paraxial parallel ray leaves at
+fn focal_length(surfaces: &[Surface]) -> Option<f64> {
+    let height = 1e-3 * surfaces[0].element.aperture;
+    let (_, d) = parallel_ray(surfaces, height)?;
+    (d.x < 0.0).then(|| height * d.z.abs() / -d.x)
+}
+
+// Radius of the widest parallel beam the lens lets through
+fn entrance_pupil_radius(surfaces: &[Surface]) -> f64 {
+    let (mut low, mut high) = (0.0, surfaces[0].element.aperture / 2.0);
+    if parallel_ray(surfaces, 1e-9).is_none() {
+        return 0.0;
+    }
+    for _ in 0..40 {
+        let middle = 0.5 * (low + high);
+        if parallel_ray(surfaces, middle).is_some() { low = middle } else { high = middle }
+    }
+    low
+}
+
+// Vertex positions for the elements with the last one film_distance from the sensor
+fn layout(elements: &[LensElement], film_distance: f64) -> Vec<Surface> {
+    let mut z = film_distance;
+    let mut surfaces = vec![];
+    for (i, element) in elements.iter().enumerate().rev() {
+        if i + 1 < elements.len() {
+            z += element.thickness;
+        }
+        surfaces.push(Surface { element: *element, z });
+    }
+    surfaces.reverse();
+    surfaces
+}
+
+fn medium(ior: f64) -> f64 {
+    if ior == 0.0 { 1.0 } else { ior }
+}
+
+// Refracts unit direction d at a surface with unit normal n facing it, None on total internal reflection
+fn refract(d: &Vec3, n: &Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = -Vec3::dot(d, n);
+    let sin2_t = eta*eta * (1.0 - cos_i*cos_i);
+    if sin2_t > 1.0 {
+        return None;
+    }
+    let cos_t = f64::sqrt(1.0 - sin2_t);
+    Some(eta*(*d) + (eta*cos_i - cos_t)*(*n))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Lens, Realistic};
+    use crate::{sampler::{PixelSampler, Sampler}, vec3::{Point3, Vec3}};
+
+    // Double Gauss 50mm f/2, after US patent 2,673,491
+    const DOUBLE_GAUSS: &str = "
+        29.475   3.76   1.67    25.2
+        84.83    0.12   1       25.2
+        19.275   4.025  1.67    23
+        40.77    3.275  1.699   23
+        12.75    5.705  1       18
+        0        4.5    0       17.1   # stop
+        -14.495  1.18   1.603   17
+        40.77    6.065  1.658   20
+        -20.385  0.19   1       20
+        437.065  3.22   1.717   20
+        -39.73   40     1       20
+    ";
+
+    fn lens() -> Lens {
+        Lens {
+            elements: Arc::new(Lens::parse_elements(DOUBLE_GAUSS).unwrap()),
+            sensor_width: 36.0,
+            sensor_height: 24.0,
+            units_per_meter: 1.0
+        }
+    }
+
+    fn camera(focus_dist: f64) -> Realistic {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        Realistic::new(origin, Point3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0), &lens(), focus_dist, 1.5).unwrap()
+    }
+
+    #[test]
+    fn center_rays_meet_at_the_focus_distance() {
+        for focus_dist in [1.0, 5.0] {
+            let cam = camera(focus_dist);
+            let mut hits = 0;
+            for index in 0..64 {
+                let mut sampler = PixelSampler::new(Sampler::Independent, 1, 0, 0, 1, index, 64);
+                let Some((r, _)) = cam.sample_ray(0.5, 0.5, &mut sampler) else { continue };
+                hits += 1;
+                let at_focus = r.at((-focus_dist - r.origin().z) / r.direction().z);
+                assert!(f64::hypot(at_focus.x, at_focus.y) < 0.002 * focus_dist, "{:?}", at_focus);
+            }
+            assert!(hits > 32);
+        }
+    }
+
+    #[test]
+    fn image_is_not_mirrored() {
+        let cam = camera(5.0);
+        for index in 0..16 {
+            let mut sampler = PixelSampler::new(Sampler::Independent, 0, 0, 0, 1, index, 16);
+            if let Some((right, _)) = cam.sample_ray(0.9, 0.5, &mut sampler) {
+                // About the angle a 50mm lens gives 14.4mm off center
+                assert!((right.direction().x / -right.direction().z - 14.4 / 50.0).abs() < 0.02);
+            }
+            if let Some((top, _)) = cam.sample_ray(0.5, 0.9, &mut sampler) {
+                assert!(top.direction().y > 0.0);
+            }
+        }
+    }
+
+    // Mean weight of the rays through a point of the image, blocked ones counting as 0
+    fn exposure(cam: &Realistic, s: f64, t: f64) -> f64 {
+        let samples = 4096;
+        (0..samples).map(|index| {
+            let mut sampler = PixelSampler::new(Sampler::Independent, 2, 0, 0, 1, index, samples);
+            cam.sample_ray(s, t, &mut sampler).map_or(0.0, |(_, weight)| weight)
+        }).sum::<f64>() / samples as f64
+    }
+
+    #[test]
+    fn center_has_unit_exposure_and_corners_fall_off() {
+        let cam = camera(5.0);
+        let center = exposure(&cam, 0.5, 0.5);
+        assert!((center - 1.0).abs() < 0.02, "{}", center);
+
+        let corner = exposure(&cam, 0.98, 0.97);
+        assert!(corner < 0.8 * center, "{}", corner);
+        // Falloff is gradual: halfway out is darker than the center, brighter than the corner
+        let halfway = exposure(&cam, 0.75, 0.5);
+        assert!(halfway < center && halfway > corner, "{}", halfway);
+    }
+
+    #[test]
+    fn stops_down_to_the_f_stop() {
+        let wide_open = lens().f_stop().unwrap();
+        assert!((wide_open - 2.0).abs() < 0.3, "{}", wide_open);
+
+        let mut stopped = lens();
+        stopped.stop_down(8.0).unwrap();
+        assert!((stopped.f_stop().unwrap() - 8.0).abs() < 0.01, "{:?}", stopped.f_stop());
+        // It can't open wider than it is built
+        assert!(lens().stop_down(1.0).is_err());
+    }
+
+    #[test]
+    fn fails_to_focus_closer_than_it_can() {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        assert!(Realistic::new(origin, Point3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0), &lens(), 0.01, 1.5).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_prescriptions() {
+        assert!(Lens::parse_elements("").is_err());
+        assert!(Lens::parse_elements("10 2 1.5").is_err());
+        assert!(Lens::parse_elements("10 2 1.5 -4").is_err());
+    }
+}
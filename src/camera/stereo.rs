@@ -0,0 +1,97 @@
+use crate::{ray::Ray, sampler::PixelSampler, util::degrees_to_radians};
+
+use super::{basis, equirectangular::Equirectangular, Camera, CameraSettings, Projection};
+
+// How the two eyes share the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StereoLayout {
+    // Left eye in the left half
+    SideBySide,
+    // Left eye in the top half
+    TopBottom
+}
+
+impl StereoLayout {
+    pub fn from_name(name: &str) -> Option<StereoLayout> {
+        match name {
+            "side-by-side" => Some(StereoLayout::SideBySide),
+            "top-bottom" => Some(StereoLayout::TopBottom),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StereoSettings {
+    pub layout: StereoLayout,
+    // Distance between the eyes, in scene units
+    pub interocular: f64,
+    // Distance of the plane that appears at screen depth, the focus distance if not set
+    pub convergence: Option<f64>
+}
+
+// Stereo pair of cameras with parallel axes, one interocular distance apart.
+// Perspective eyes get off-axis frustums that meet at the convergence distance,
+// which avoids the vertical parallax of toed-in cameras. An equirectangular
+// camera becomes omnidirectional stereo (ODS): every ray starts on the circle
+// the eyes sweep when turning the head towards it.
+pub struct Stereo {
+    left: Box<Camera>,
+    right: Box<Camera>,
+    layout: StereoLayout,
+    // Horizontal offset of each eye's image window, as a fraction of its width
+    shift: f64
+}
+
+impl Stereo {
+    pub fn sample_ray(&self, s: f64, t: f64, sampler: &mut PixelSampler) -> Option<(Ray, f64)> {
+        match self.layout {
+            StereoLayout::SideBySide if s < 0.5 => self.left.sample_ray(2.0*s + self.shift, t, sampler),
+            StereoLayout::SideBySide => self.right.sample_ray(2.0*s - 1.0 - self.shift, t, sampler),
+            StereoLayout::TopBottom if t >= 0.5 => self.left.sample_ray(s + self.shift, 2.0*t - 1.0, sampler),
+            StereoLayout::TopBottom => self.right.sample_ray(s - self.shift, 2.0*t, sampler)
+        }
+    }
+
+    pub fn new(camera: &CameraSettings, stereo: &StereoSettings, aspect_ratio: f64) -> Result<Stereo, String> {
+        let eye_aspect_ratio = match stereo.layout {
+            StereoLayout::SideBySide => aspect_ratio / 2.0,
+            StereoLayout::TopBottom => aspect_ratio * 2.0
+        };
+        let half = stereo.interocular / 2.0;
+
+        let eye = |offset: f64| -> Result<Camera, String> {
+            let mono = CameraSettings { stereo: None, ..camera.clone() };
+            match camera.projection {
+                Projection::Equirectangular => Ok(Camera::Equirectangular(
+                    Equirectangular::new(camera.look_from, camera.look_at, camera.vup, offset)
+                )),
+                _ => {
+                    let (u, _, _) = basis(camera.look_from, camera.look_at, camera.vup);
+                    let moved = CameraSettings {
+                        look_from: camera.look_from + offset*u,
+                        look_at: camera.look_at + offset*u,
+                        ..mono
+                    };
+                    moved.build(eye_aspect_ratio)
+                }
+            }
+        };
+
+        let shift = match camera.projection {
+            Projection::Perspective => {
+                let convergence = stereo.convergence.unwrap_or(camera.focus_dist);
+                let width = 2.0 * convergence * f64::tan(degrees_to_radians(camera.vfov)/2.0) * eye_aspect_ratio;
+                half / width
+            }
+            _ => 0.0
+        };
+
+        Ok(Stereo {
+            left: Box::new(eye(-half)?),
+            right: Box::new(eye(half)?),
+            layout: stereo.layout,
+            shift
+        })
+    }
+}
@@ -0,0 +1,148 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write}
+};
+
+use crate::{bytes::{read_u32, write_f64, write_u32, write_u64}, film::{Film, Pixel}, options::Options};
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 7;
+
+// Render state written between passes. Samples draw their random numbers from
+// streams derived from the seed, pixel and sample index, so the seed and the
+// next pass to render are the complete RNG state: resuming continues with
+// exactly the samples an uninterrupted render would have taken.
+pub struct Checkpoint {
+    pub film: Film,
+    pub next_pass: u32
+}
+
+pub fn save(path: &str, film: &Film, options: &Options, next_pass: u32) -> io::Result<()> {
+    // Write to a temporary file first so a kill mid-write can't corrupt the last checkpoint
+    let tmp_path = format!("{}.tmp", path);
+    let mut w = BufWriter::new(File::create(&tmp_path)?);
+
+    w.write_all(MAGIC)?;
+    write_u32(&mut w, VERSION)?;
+    write_header(&mut w, options)?;
+    write_u32(&mut w, next_pass)?;
+
+    for pixel in film.pixels() {
+        pixel.write_to(&mut w)?;
+    }
+    w.into_inner()?.sync_all()?;
+
+    fs::rename(tmp_path, path)
+}
+
+pub fn load(path: &str, options: &Options) -> io::Result<Checkpoint> {
+    let mut r = BufReader::new(File::open(path)?);
+
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC || read_u32(&mut r)? != VERSION {
+        return Err(invalid("not a render checkpoint"));
+    }
+
+    // The checkpoint is only valid for the settings that determine which samples are taken
+    let mut expected = Vec::new();
+    write_header(&mut expected, options)?;
+    let mut header = vec![0; expected.len()];
+    r.read_exact(&mut header)?;
+    if header != expected {
+        return Err(invalid("checkpoint was written with different render settings"));
+    }
+    let next_pass = read_u32(&mut r)?;
+
+    let mut film = Film::new(options.width, options.height);
+    for pixel in film.pixels_mut() {
+        *pixel = Pixel::read_from(&mut r)?;
+    }
+
+    Ok(Checkpoint { film, next_pass })
+}
+
+fn write_header(w: &mut impl Write, options: &Options) -> io::Result<()> {
+    write_u32(w, options.width)?;
+    write_u32(w, options.height)?;
+    write_u32(w, options.samples_per_pixel)?;
+    write_u32(w, options.min_samples_per_pixel)?;
+    write_u32(w, options.max_samples())?;
+    write_u32(w, options.pass_samples)?;
+    write_f64(w, options.noise_threshold)?;
+    write_u32(w, options.max_depth)?;
+    write_u64(w, options.seed)?;
+    write_u32(w, options.frame)?;
+    write_f64(w, options.exposure)?;
+    w.write_all(format!("{:?}", options.sampler).as_bytes())?;
+    w.write_all(format!("{:?}", options.filter).as_bytes())?;
+    w.write_all(format!("{:?}", options.crop).as_bytes())?;
+    // Everything else on the command line, which decides the scene, camera and lights
+    write_u64(w, settings_hash(options))
+}
+
+// FNV-1a over the job's arguments, which leave out how the render is run:
+// checkpointing, previews, output files and workers
+fn settings_hash(options: &Options) -> u64 {
+    let mut hash = 0xcbf29ce484222325;
+    for byte in options.job_args().iter().flat_map(|arg| arg.bytes().chain([0])) {
+        hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::load;
+    use crate::{options::Options, render::render, test_scene};
+
+    #[test]
+    fn resumed_render_matches_uninterrupted() {
+        let (scene, cam, mut options) = test_scene::glass_sphere();
+        options.pass_samples = 2;
+        let path = std::env::temp_dir().join(format!("raytraced_rust_resume_{}.ckpt", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+
+        let full = render(&scene, &cam, &options, None);
+
+        // Stop after the first pass, then pick up from its checkpoint
+        options.checkpoint = Some(path.clone());
+        options.stop_after_passes = Some(1);
+        render(&scene, &cam, &options, None);
+
+        // Resuming with settings that change which samples are taken is refused
+        let changes = [
+            |o: &mut Options| o.samples_per_pixel += 1,
+            |o: &mut Options| o.min_samples_per_pixel = 2,
+            |o: &mut Options| o.noise_threshold = 0.5,
+            |o: &mut Options| o.max_samples_per_pixel = Some(8),
+            |o: &mut Options| o.args = vec![String::from("--sky")]
+        ];
+        for change in changes {
+            let mut other = options.clone();
+            change(&mut other);
+            assert!(load(&path, &other).is_err());
+        }
+        // How the render is run doesn't matter
+        let mut other = options.clone();
+        other.args = ["--resume", "--checkpoint-interval", "5", "--preview-interval", "1"].map(String::from).to_vec();
+        assert!(load(&path, &other).is_ok());
+
+        let resume = load(&path, &options).unwrap();
+        options.checkpoint = None;
+        options.stop_after_passes = None;
+        let resumed = render(&scene, &cam, &options, Some(resume));
+        std::fs::remove_file(&path).unwrap();
+
+        for y in 0..options.height {
+            for x in 0..options.width {
+                assert_eq!(full.pixel_color(x, y), resumed.pixel_color(x, y));
+                assert_eq!(full.stats(x, y), resumed.stats(x, y));
+            }
+        }
+    }
+}
@@ -0,0 +1,441 @@
+use std::{
+    collections::VecDeque,
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    ops::Range,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration
+};
+
+use crate::{
+    bytes::{read_string, read_u32, write_string, write_u32},
+    camera::Camera,
+    film::{FilmTile, PixelStats},
+    options::Options,
+    render,
+    scene::Scene,
+    tile::Region
+};
+
+// Coordinator/worker rendering over TCP.
+//
+// The coordinator opens one connection per worker thread and sends each the
+// job: the command line arguments of the render. The scene is procedural, so a
+// worker rebuilds exactly the same world and camera from them. After that the
+// coordinator hands out tiles one at a time (region, sample range and the
+// statistics adaptive sampling needs) and gets the splatted float tile back,
+// while its own threads render tiles from the same queue.
+// Tiles are merged in tile order no matter who rendered them, so the result is
+// identical to a local render.
+//
+// Workers read the files the job names themselves, from the same paths: the
+// --environment map, the ies= profiles of --light, the --lens prescription,
+// --aperture-mask and the --animation script have to be there too.
+
+const JOB_MAGIC: &[u8; 4] = b"RTJB";
+const MESSAGE_TILE: u32 = 1;
+const MESSAGE_DONE: u32 = 0;
+
+// Limits on what a job may claim to hold, so a bad message can't make a worker
+// allocate gigabytes
+const MAX_ARGS: u32 = 4096;
+const MAX_ARG_LENGTH: usize = 64 * 1024;
+
+// Builds the world and camera for a set of options, or says why it can't
+pub type SceneBuilder = fn(&Options) -> Result<(Scene, Camera), String>;
+
+// A job a worker has built the scene for, shared by the connections that
+// render it
+struct Job {
+    args: Vec<String>,
+    options: Options,
+    scene: Scene,
+    cam: Camera
+}
+
+// Runs a worker, serving coordinators on `address` until the process is killed.
+// A coordinator that sends nothing for `timeout` is dropped.
+pub fn serve(address: &str, build_scene: SceneBuilder, timeout: Duration) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    println!("Worker listening on {}", listener.local_addr()?);
+    serve_listener(listener, build_scene, timeout)
+}
+
+fn serve_listener(listener: TcpListener, build_scene: SceneBuilder, timeout: Duration) -> io::Result<()> {
+    // A coordinator opens a connection per worker thread, all for the same job
+    let last_job: Arc<Mutex<Option<Arc<Job>>>> = Arc::default();
+    for stream in listener.incoming() {
+        // A failed accept only loses that connection
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("Failed to accept a connection: {}", err);
+                continue;
+            }
+        };
+        let last_job = last_job.clone();
+        thread::spawn(move || {
+            let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+            if let Err(err) = handle_connection(stream, build_scene, timeout, &last_job) {
+                eprintln!("Connection from {} failed: {}", peer, err);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle_connection(stream: TcpStream, build_scene: SceneBuilder, timeout: Duration, last_job: &Mutex<Option<Arc<Job>>>) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    // Tell the coordinator how many connections this worker can keep busy
+    write_u32(&mut writer, rayon::current_num_threads() as u32)?;
+    writer.flush()?;
+
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != JOB_MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a render job"));
+    }
+    let count = read_u32(&mut reader)?;
+    if count > MAX_ARGS {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "too many arguments"));
+    }
+    let args = (0..count).map(|_| read_string(&mut reader, MAX_ARG_LENGTH)).collect::<io::Result<Vec<_>>>()?;
+    let job = {
+        // The other connections of the job wait here while the first builds the scene
+        let mut last_job = last_job.lock().unwrap();
+        match last_job.as_ref() {
+            Some(job) if job.args == args => job.clone(),
+            _ => {
+                let options = Options::from_args(args.clone().into_iter())
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+                let (scene, cam) = build_scene(&options).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+                let job = Arc::new(Job { args, options, scene, cam });
+                *last_job = Some(job.clone());
+                job
+            }
+        }
+    };
+    let Job { options, scene, cam, .. } = job.as_ref();
+
+    while read_u32(&mut reader)? == MESSAGE_TILE {
+        let region = Region::new(read_u32(&mut reader)?, read_u32(&mut reader)?, read_u32(&mut reader)?, read_u32(&mut reader)?);
+        let samples = read_u32(&mut reader)?..read_u32(&mut reader)?;
+        if region.x0 > region.x1 || region.y0 > region.y1 || region.x1 > options.width || region.y1 > options.height {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "tile is outside the image"));
+        }
+        let previous = (0..region.width() * region.height())
+            .map(|_| PixelStats::read_from(&mut reader))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let tile = render::render_tile(scene, cam, options, &region, &previous, samples);
+        tile.write_to(&mut writer)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+// Connections to the workers of a coordinator
+pub struct WorkerPool {
+    connections: Vec<Connection>
+}
+
+struct Connection {
+    address: String,
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+    // Size of the image, which every tile has to fit on
+    film_width: u32,
+    film_height: u32
+}
+
+impl Connection {
+    // A worker that doesn't answer within `timeout` counts as lost
+    fn open(address: &str, timeout: Duration, film_width: u32, film_height: u32) -> io::Result<(Connection, u32)> {
+        let socket_address = address.to_socket_addrs()?.next()
+            .ok_or(io::Error::new(io::ErrorKind::NotFound, "address resolves to nothing"))?;
+        let stream = TcpStream::connect_timeout(&socket_address, timeout)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let mut connection = Connection {
+            address: address.to_string(),
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+            film_width,
+            film_height
+        };
+        let threads = read_u32(&mut connection.reader)?;
+        Ok((connection, threads))
+    }
+
+    fn send_job(&mut self, args: &[String]) -> io::Result<()> {
+        self.writer.write_all(JOB_MAGIC)?;
+        write_u32(&mut self.writer, args.len() as u32)?;
+        for arg in args {
+            write_string(&mut self.writer, arg)?;
+        }
+        self.writer.flush()
+    }
+
+    fn render_tile(&mut self, region: &Region, samples: Range<u32>, previous: &[PixelStats]) -> io::Result<FilmTile> {
+        write_u32(&mut self.writer, MESSAGE_TILE)?;
+        for value in [region.x0, region.y0, region.x1, region.y1, samples.start, samples.end] {
+            write_u32(&mut self.writer, value)?;
+        }
+        for stats in previous {
+            stats.write_to(&mut self.writer)?;
+        }
+        self.writer.flush()?;
+
+        FilmTile::read_from(&mut self.reader, self.film_width, self.film_height)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Best effort, the worker may already be gone
+        let _ = write_u32(&mut self.writer, MESSAGE_DONE).and_then(|_| self.writer.flush());
+    }
+}
+
+impl WorkerPool {
+    // Connects to the workers of the options and sends them the job, skipping
+    // the ones that can't be reached
+    pub fn connect(options: &Options) -> WorkerPool {
+        let args = options.job_args();
+        let timeout = Duration::from_secs_f64(options.worker_timeout);
+        let open = |address: &str| Connection::open(address, timeout, options.width, options.height);
+        let mut connections = Vec::new();
+        for address in &options.workers {
+            let result = open(address).and_then(|(mut first, threads)| {
+                first.send_job(&args)?;
+                let mut opened = vec![first];
+                for _ in 1..threads {
+                    let (mut connection, _) = open(address)?;
+                    connection.send_job(&args)?;
+                    opened.push(connection);
+                }
+                Ok(opened)
+            });
+            match result {
+                Ok(opened) => {
+                    println!("Connected to worker {} ({} threads)", address, opened.len());
+                    connections.extend(opened);
+                }
+                Err(err) => eprintln!("Skipping worker {}: {}", address, err)
+            }
+        }
+        WorkerPool { connections }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    // Renders the tiles at the indices in `queue` on the workers until it runs
+    // empty, calling on_tile with each finished tile and its index. Local threads
+    // may take from the same queue meanwhile. A connection that fails puts its
+    // tile back for the others and is dropped.
+    pub fn render_tiles(
+        &mut self,
+        tiles: &[Region],
+        samples: Range<u32>,
+        queue: &Mutex<VecDeque<usize>>,
+        previous_stats: &(dyn Fn(&Region) -> Vec<PixelStats> + Sync),
+        on_tile: &(dyn Fn(usize, FilmTile) + Sync)
+    ) {
+        let connections = std::mem::take(&mut self.connections);
+
+        let survivors = thread::scope(|scope| {
+            let handles: Vec<_> = connections.into_iter().map(|mut connection| {
+                let samples = samples.clone();
+                scope.spawn(move || loop {
+                    let Some(index) = queue.lock().unwrap().pop_front() else {
+                        return Some(connection);
+                    };
+                    let region = &tiles[index];
+                    match connection.render_tile(region, samples.clone(), &previous_stats(region)) {
+                        Ok(tile) => on_tile(index, tile),
+                        Err(err) => {
+                            // Timeouts show up as WouldBlock on some platforms
+                            if matches!(err.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock) {
+                                eprintln!("Worker {} timed out, rescheduling its tile", connection.address);
+                            } else {
+                                eprintln!("Worker {} failed, rescheduling its tile: {}", connection.address, err);
+                            }
+                            queue.lock().unwrap().push_front(index);
+                            return None;
+                        }
+                    }
+                })
+            }).collect();
+            handles.into_iter().filter_map(|handle| handle.join().unwrap()).collect()
+        });
+        self.connections = survivors;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{ErrorKind, Read},
+        net::{TcpListener, TcpStream},
+        sync::Arc,
+        thread,
+        time::{Duration, Instant}
+    };
+
+    use super::{serve_listener, WorkerPool, MAX_ARG_LENGTH};
+    use crate::{
+        bytes::{read_string, write_u32},
+        film::FilmTile,
+        camera::{Camera, perspective::Perspective},
+        hittable_list::HittableList,
+        light::tree::LightTree,
+        material::{Material, lambertian::Lambertian, metal::Metal},
+        options::Options,
+        render::render,
+        scene::Scene,
+        sphere::Sphere,
+        vec3::{Color, Point3, Vec3}
+    };
+
+    fn build_scene(options: &Options) -> Result<(Scene, Camera), String> {
+        let mut world = HittableList::new();
+        let ground = Arc::new(Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let metal = Arc::new(Material::Metal(Metal::new(Color::new(0.8, 0.6, 0.2), 0.3)));
+        world.add(Box::new(Sphere::new(Point3::new(0.0, -100.5, -1.0), 100.0, &ground)));
+        world.add(Box::new(Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, &metal)));
+
+        let cam = Camera::Perspective(Perspective::new(Point3::new(0.0, 0.0, 1.0), Point3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0),
+            60.0, options.aspect_ratio, 0.0, 2.0));
+        Ok((Scene { world, emitters: LightTree::default() }, cam))
+    }
+
+    fn options(workers: Vec<String>) -> Options {
+        let args = ["--width", "30", "--samples", "4", "--min-samples", "2", "--pass-samples", "2", "--tile-size", "8", "--filter", "tent",
+            "--worker-timeout", "0.5"];
+        let mut args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        if !workers.is_empty() {
+            args.push(String::from("--workers"));
+            args.push(workers.join(","));
+        }
+        Options::from_args(args.into_iter()).unwrap()
+    }
+
+    fn spawn_worker() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || serve_listener(listener, build_scene, Duration::from_secs(5)));
+        address
+    }
+
+    // Accepts connections like a worker, then dies during the first tile
+    fn spawn_dying_worker() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            write_u32(&mut stream, 1).unwrap();
+            let mut buffer = [0; 64];
+            let _ = stream.read(&mut buffer);
+        });
+        address
+    }
+
+    // Accepts connections like a worker, then never answers
+    fn spawn_hanging_worker() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            write_u32(&mut stream, 1).unwrap();
+            // Swallow everything until the coordinator gives up on us
+            let mut buffer = [0; 64];
+            while matches!(stream.read(&mut buffer), Ok(n) if n > 0) {}
+        });
+        address
+    }
+
+    fn assert_same_as_local(workers: Vec<String>) {
+        let local_options = options(Vec::new());
+        let (scene, cam) = build_scene(&local_options).unwrap();
+        let local = render(&scene, &cam, &local_options, None);
+
+        let remote_options = options(workers);
+        let remote = render(&scene, &cam, &remote_options, None);
+
+        for y in 0..local_options.height {
+            for x in 0..local_options.width {
+                assert_eq!(local.pixel_color(x, y), remote.pixel_color(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn workers_match_local_render() {
+        assert_same_as_local(vec![spawn_worker(), spawn_worker()]);
+    }
+
+    #[test]
+    fn survives_dying_worker() {
+        assert_same_as_local(vec![spawn_dying_worker(), spawn_worker()]);
+    }
+
+    #[test]
+    fn survives_hanging_worker() {
+        assert_same_as_local(vec![spawn_hanging_worker(), spawn_worker()]);
+    }
+
+    #[test]
+    fn rejects_oversized_messages() {
+        let mut message = Vec::new();
+        write_u32(&mut message, u32::MAX).unwrap();
+        let err = read_string(&mut message.as_slice(), MAX_ARG_LENGTH).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // A tile claiming to be larger than the image
+        let mut message = Vec::new();
+        for value in [0, 0, 100_000, 100_000] {
+            write_u32(&mut message, value).unwrap();
+        }
+        let err = FilmTile::read_from(&mut message.as_slice(), 30, 20).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn worker_drops_a_silent_coordinator() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve_listener(listener, build_scene, Duration::from_millis(200)));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let start = Instant::now();
+        // The thread count, then the worker hangs up instead of waiting for the job forever
+        let mut buffer = [0; 64];
+        let mut received = 0;
+        while let Ok(n) = stream.read(&mut buffer) {
+            if n == 0 {
+                break;
+            }
+            received += n;
+        }
+        assert_eq!(received, 4);
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn falls_back_to_local_without_workers() {
+        let pool = WorkerPool::connect(&options(vec![spawn_dying_worker()]));
+        assert!(!pool.is_empty());
+
+        assert_same_as_local(vec![spawn_dying_worker()]);
+    }
+}
@@ -0,0 +1,118 @@
+// Piecewise constant distribution over [0, 1), sampled by inverting its CDF
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    function: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64
+}
+
+impl Distribution1D {
+    // A function that is zero everywhere gives the uniform distribution
+    pub fn new(function: &[f64]) -> Distribution1D {
+        let n = function.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + function[i].abs() / n as f64;
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 { *c / integral } else { i as f64 / n as f64 };
+        }
+        Distribution1D { function: function.to_vec(), cdf, integral }
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    // Returns the point, its density and the index of the piece it is in
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let n = self.function.len();
+        let i = usize::min(self.cdf.partition_point(|&c| c <= u), n) - 1;
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0.0 { (u - self.cdf[i]) / width } else { 0.0 };
+        let pdf = if self.integral > 0.0 { self.function[i].abs() / self.integral } else { 1.0 };
+        ((i as f64 + offset) / n as f64, pdf, i)
+    }
+
+    // Density at x in [0, 1)
+    pub fn pdf(&self, x: f64) -> f64 {
+        let n = self.function.len();
+        let i = usize::min((x * n as f64) as usize, n - 1);
+        if self.integral > 0.0 { self.function[i].abs() / self.integral } else { 1.0 }
+    }
+}
+
+// Piecewise constant distribution over [0, 1)^2 given row by row: a row is
+// picked from the marginal distribution, then a column within it
+#[derive(Debug, Clone)]
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D
+}
+
+impl Distribution2D {
+    pub fn new(function: &[f64], width: usize, height: usize) -> Distribution2D {
+        let rows: Vec<Distribution1D> = function.chunks(width).take(height).map(Distribution1D::new).collect();
+        let marginal = Distribution1D::new(&rows.iter().map(|row| row.integral()).collect::<Vec<_>>());
+        Distribution2D { rows, marginal }
+    }
+
+    // Mean of the function over the unit square
+    pub fn integral(&self) -> f64 {
+        self.marginal.integral()
+    }
+
+    // Returns the point (x along rows, y down the rows) and its density
+    pub fn sample(&self, u: (f64, f64)) -> ((f64, f64), f64) {
+        let (y, row_pdf, row) = self.marginal.sample(u.1);
+        let (x, pdf, _) = self.rows[row].sample(u.0);
+        ((x, y), row_pdf * pdf)
+    }
+
+    // Density at the point, the same `sample` returns for it
+    pub fn pdf(&self, p: (f64, f64)) -> f64 {
+        let n = self.rows.len();
+        let row = usize::min((p.1 * n as f64) as usize, n - 1);
+        self.marginal.pdf(p.1) * self.rows[row].pdf(p.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Distribution1D, Distribution2D};
+
+    #[test]
+    fn samples_follow_the_function() {
+        let distribution = Distribution1D::new(&[1.0, 0.0, 3.0]);
+        let mut counts = [0; 3];
+        for i in 0..1000 {
+            let (x, pdf, index) = distribution.sample((i as f64 + 0.5) / 1000.0);
+            assert_eq!(index, (x * 3.0) as usize);
+            assert!(pdf > 0.0);
+            counts[index] += 1;
+        }
+        assert_eq!(counts, [250, 0, 750]);
+    }
+
+    #[test]
+    fn zero_function_is_uniform() {
+        let distribution = Distribution1D::new(&[0.0; 4]);
+        let (x, pdf, _) = distribution.sample(0.6);
+
+        assert!((x - 0.6).abs() < 1e-12);
+        assert_eq!(pdf, 1.0);
+    }
+
+    #[test]
+    fn two_dimensional_density() {
+        // Only the bottom right of 2x2 cells is lit
+        let distribution = Distribution2D::new(&[0.0, 0.0, 0.0, 2.0], 2, 2);
+        let ((x, y), pdf) = distribution.sample((0.3, 0.8));
+
+        assert!(x >= 0.5 && y >= 0.5);
+        assert!((pdf - 4.0).abs() < 1e-12);
+        assert!((distribution.pdf((x, y)) - pdf).abs() < 1e-12);
+        assert_eq!(distribution.pdf((0.2, 0.2)), 0.0);
+    }
+}
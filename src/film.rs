@@ -0,0 +1,352 @@
+use std::io::{self, Read, Write};
+
+use crate::{
+    bytes::{read_f64, read_u32, write_f64, write_u32},
+    filter::Filter,
+    tile::Region,
+    util::clamp,
+    vec3::Color
+};
+
+// Weighted sum of all samples splatted onto a pixel
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Pixel {
+    pub color: Color,
+    pub weight: f64,
+    // Plain sum and number of the same samples, for when negative filter lobes
+    // cancel the weights out
+    pub unweighted: Color,
+    pub count: u32,
+    pub stats: PixelStats
+}
+
+impl Pixel {
+    pub fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        write_f64(w, self.color.x)?;
+        write_f64(w, self.color.y)?;
+        write_f64(w, self.color.z)?;
+        write_f64(w, self.weight)?;
+        write_f64(w, self.unweighted.x)?;
+        write_f64(w, self.unweighted.y)?;
+        write_f64(w, self.unweighted.z)?;
+        write_u32(w, self.count)?;
+        self.stats.write_to(w)
+    }
+
+    pub fn read_from(r: &mut impl Read) -> io::Result<Pixel> {
+        Ok(Pixel {
+            color: Color::new(read_f64(r)?, read_f64(r)?, read_f64(r)?),
+            weight: read_f64(r)?,
+            unweighted: Color::new(read_f64(r)?, read_f64(r)?, read_f64(r)?),
+            count: read_u32(r)?,
+            stats: PixelStats::read_from(r)?
+        })
+    }
+}
+
+// Running luminance statistics of the samples taken inside a pixel (Welford),
+// used to decide when a pixel has converged
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PixelStats {
+    pub samples: u32,
+    pub mean: f64,
+    pub m2: f64
+}
+
+impl PixelStats {
+    pub fn add(&mut self, luminance: f64) {
+        self.samples += 1;
+        let delta = luminance - self.mean;
+        self.mean += delta / self.samples as f64;
+        self.m2 += delta * (luminance - self.mean);
+    }
+
+    // Combines the statistics of two disjoint sets of samples (Chan et al.)
+    pub fn merge(&mut self, other: &PixelStats) {
+        if other.samples == 0 {
+            return;
+        }
+        let n = self.samples as f64 + other.samples as f64;
+        let delta = other.mean - self.mean;
+        self.m2 += other.m2 + delta*delta * self.samples as f64 * other.samples as f64 / n;
+        self.mean += delta * other.samples as f64 / n;
+        self.samples += other.samples;
+    }
+
+    // Standard error of the pixel mean after gamma 2 display encoding, so the
+    // noise threshold means the same in dark and bright areas
+    pub fn display_error(&self) -> f64 {
+        if self.samples < 2 {
+            return f64::INFINITY;
+        }
+        let variance = self.m2 / (self.samples - 1) as f64;
+        let standard_error = f64::sqrt(variance / self.samples as f64);
+        standard_error / (2.0 * f64::sqrt(f64::max(self.mean, 0.0)) + 1e-3)
+    }
+
+    pub fn converged(&self, min_samples: u32, noise_threshold: f64) -> bool {
+        noise_threshold > 0.0 && self.samples >= min_samples && self.display_error() <= noise_threshold
+    }
+
+    pub fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        write_u32(w, self.samples)?;
+        write_f64(w, self.mean)?;
+        write_f64(w, self.m2)
+    }
+
+    pub fn read_from(r: &mut impl Read) -> io::Result<PixelStats> {
+        Ok(PixelStats { samples: read_u32(r)?, mean: read_f64(r)?, m2: read_f64(r)? })
+    }
+}
+
+// Float accumulation buffer for the whole image. Row 0 is the top of the image.
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pixels: Vec<Pixel>
+}
+
+// A rectangular piece of the film that a single thread splats into. It extends
+// past the pixels it renders by the filter radius, so samples near the edge can
+// reach the neighbouring pixels owned by other tiles.
+pub struct FilmTile {
+    x0: i64,
+    y0: i64,
+    width: i64,
+    height: i64,
+    pixels: Vec<Pixel>
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Film {
+        Film { width, height, pixels: vec![Pixel::default(); (width * height) as usize] }
+    }
+
+    pub fn merge_tile(&mut self, tile: &FilmTile) {
+        for ty in 0..tile.height {
+            for tx in 0..tile.width {
+                let src = tile.pixels[(ty * tile.width + tx) as usize];
+                let dst = &mut self.pixels[((tile.y0 + ty) * self.width as i64 + tile.x0 + tx) as usize];
+                dst.color += src.color;
+                dst.weight += src.weight;
+                dst.unweighted += src.unweighted;
+                dst.count += src.count;
+                dst.stats.merge(&src.stats);
+            }
+        }
+    }
+
+    // Final pixel value: the filter weighted average of its samples, or their
+    // plain average where the weights add up to nothing
+    pub fn pixel_color(&self, x: u32, y: u32) -> Color {
+        let pixel = self.pixels[(y * self.width + x) as usize];
+        if pixel.weight > 0.0 {
+            pixel.color / pixel.weight
+        } else if pixel.count > 0 {
+            pixel.unweighted / pixel.count as f64
+        } else {
+            Color::default()
+        }
+    }
+
+    pub fn pixels(&self) -> &[Pixel] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [Pixel] {
+        &mut self.pixels
+    }
+
+    pub fn stats(&self, x: u32, y: u32) -> PixelStats {
+        self.pixels[(y * self.width + x) as usize].stats
+    }
+
+    pub fn total_samples(&self) -> u64 {
+        self.pixels.iter().map(|pixel| pixel.stats.samples as u64).sum()
+    }
+
+    pub fn region(&self) -> Region {
+        Region::new(0, 0, self.width, self.height)
+    }
+
+    // Writes the pixels of `region` to an image file
+    pub fn save(&self, path: &str, region: Region) {
+        image::save_buffer(path, &self.to_rgb8(region), region.width(), region.height(), image::ColorType::Rgb8).unwrap();
+    }
+
+    pub fn save_heatmap(&self, path: &str, region: Region, max_samples: u32) {
+        image::save_buffer(path, &self.heatmap_rgb8(region, max_samples), region.width(), region.height(), image::ColorType::Rgb8).unwrap();
+    }
+
+    pub fn to_rgb8(&self, region: Region) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(region.width() as usize * region.height() as usize * 3);
+        for y in region.y0..region.y1 {
+            for x in region.x0..region.x1 {
+                let c = self.pixel_color(x, y).translate(1);
+                bytes.extend_from_slice(&[c.x as u8, c.y as u8, c.z as u8]);
+            }
+        }
+        bytes
+    }
+
+    // Visualizes the number of samples each pixel received, from black (none)
+    // over red and yellow to white (max_samples)
+    pub fn heatmap_rgb8(&self, region: Region, max_samples: u32) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(region.width() as usize * region.height() as usize * 3);
+        for y in region.y0..region.y1 {
+            for x in region.x0..region.x1 {
+                let samples = self.pixels[(y * self.width + x) as usize].stats.samples;
+                let t = 3.0 * samples as f64 / max_samples as f64;
+                let c = Color::new(t, t - 1.0, t - 2.0);
+                bytes.extend_from_slice(&[
+                    (255.0 * clamp(c.x, 0.0, 1.0)) as u8,
+                    (255.0 * clamp(c.y, 0.0, 1.0)) as u8,
+                    (255.0 * clamp(c.z, 0.0, 1.0)) as u8
+                ]);
+            }
+        }
+        bytes
+    }
+}
+
+impl FilmTile {
+    // Creates a tile for rendering the pixels of `region`, padded by the filter
+    // radius and clipped to a film of film_width * film_height
+    pub fn new(region: Region, film_width: u32, film_height: u32, filter: &Filter) -> FilmTile {
+        let margin = filter.radius().ceil() as i64;
+        let x0 = i64::max(region.x0 as i64 - margin, 0);
+        let y0 = i64::max(region.y0 as i64 - margin, 0);
+        let x1 = i64::min(region.x1 as i64 + margin, film_width as i64);
+        let y1 = i64::min(region.y1 as i64 + margin, film_height as i64);
+
+        let width = x1 - x0;
+        let height = y1 - y0;
+        FilmTile { x0, y0, width, height, pixels: vec![Pixel::default(); (width * height) as usize] }
+    }
+
+    // Splats a sample at raster position (px, py) onto every pixel of the tile within the filter radius
+    pub fn add_sample(&mut self, px: f64, py: f64, color: Color, filter: &Filter) {
+        let radius = filter.radius();
+
+        // Pixel i is covered when its center i + 0.5 lies in (p - radius, p + radius]
+        let x0 = i64::max((px - 0.5 - radius).floor() as i64 + 1, self.x0);
+        let x1 = i64::min((px - 0.5 + radius).floor() as i64, self.x0 + self.width - 1);
+        let y0 = i64::max((py - 0.5 - radius).floor() as i64 + 1, self.y0);
+        let y1 = i64::min((py - 0.5 + radius).floor() as i64, self.y0 + self.height - 1);
+
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let weight = filter.evaluate(x as f64 + 0.5 - px, y as f64 + 0.5 - py);
+                if weight == 0.0 {
+                    continue;
+                }
+                let pixel = &mut self.pixels[((y - self.y0) * self.width + x - self.x0) as usize];
+                pixel.color += weight * color;
+                pixel.weight += weight;
+                pixel.unweighted += color;
+                pixel.count += 1;
+            }
+        }
+    }
+
+    pub fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        for value in [self.x0, self.y0, self.width, self.height] {
+            write_u32(w, value as u32)?;
+        }
+        for pixel in &self.pixels {
+            pixel.write_to(w)?;
+        }
+        Ok(())
+    }
+
+    // Reads a tile that must fit on a film of film_width * film_height
+    pub fn read_from(r: &mut impl Read, film_width: u32, film_height: u32) -> io::Result<FilmTile> {
+        let x0 = read_u32(r)? as i64;
+        let y0 = read_u32(r)? as i64;
+        let width = read_u32(r)? as i64;
+        let height = read_u32(r)? as i64;
+        if x0 + width > film_width as i64 || y0 + height > film_height as i64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "tile does not fit on the film"));
+        }
+        let pixels = (0..width * height).map(|_| Pixel::read_from(r)).collect::<io::Result<_>>()?;
+        Ok(FilmTile { x0, y0, width, height, pixels })
+    }
+
+    // Records the statistics of the samples taken inside pixel (x, y)
+    pub fn add_stats(&mut self, x: u32, y: u32, stats: &PixelStats) {
+        let index = ((y as i64 - self.y0) * self.width + x as i64 - self.x0) as usize;
+        self.pixels[index].stats.merge(stats);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Film, FilmTile, PixelStats};
+    use crate::{filter::Filter, render::render, test_scene, vec3::Color};
+
+    #[test]
+    fn merge_matches_sequential() {
+        let values = [0.1, 0.5, 0.2, 0.9, 0.4, 0.0, 1.3];
+        let mut all = PixelStats::default();
+        let mut first = PixelStats::default();
+        let mut second = PixelStats::default();
+
+        for (i, &v) in values.iter().enumerate() {
+            all.add(v);
+            if i < 3 { first.add(v) } else { second.add(v) }
+        }
+        first.merge(&second);
+
+        assert_eq!(first.samples, all.samples);
+        assert!((first.mean - all.mean).abs() < 1e-12);
+        assert!((first.m2 - all.m2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn constant_pixel_converges_at_min_samples() {
+        let mut stats = PixelStats::default();
+
+        for _ in 0..4 {
+            stats.add(0.7);
+        }
+
+        assert!(!stats.converged(8, 0.01));
+        for _ in 0..4 {
+            stats.add(0.7);
+        }
+        assert!(stats.converged(8, 0.01));
+        assert!(!stats.converged(8, 0.0));
+    }
+
+    #[test]
+    fn noisy_pixels_get_the_samples_converged_ones_leave() {
+        let (scene, cam, mut options) = test_scene::glass_sphere();
+        options.samples_per_pixel = 16;
+        options.min_samples_per_pixel = 4;
+        options.pass_samples = 4;
+        options.noise_threshold = 0.02;
+        options.max_samples_per_pixel = Some(64);
+
+        let film = render(&scene, &cam, &options, None);
+
+        let samples: Vec<u32> = film.pixels().iter().map(|pixel| pixel.stats.samples).collect();
+        assert!(film.total_samples() <= 16 * samples.len() as u64);
+        assert!(samples.iter().any(|&n| n < 16));
+        assert!(samples.iter().any(|&n| n > 16));
+        assert!(samples.iter().all(|&n| n <= 64));
+    }
+
+    #[test]
+    fn negative_weights_fall_back_to_the_plain_average() {
+        let filter = Filter::from_name("lanczos", 3.0).unwrap();
+        let mut film = Film::new(8, 1);
+        let mut tile = FilmTile::new(film.region(), 8, 1, &filter);
+        // Pixel 4 is 1.5 pixels away, in the filter's first negative lobe
+        tile.add_sample(6.0, 0.5, Color::new(0.2, 0.4, 0.6), &filter);
+        film.merge_tile(&tile);
+
+        assert!(film.pixels()[4].weight < 0.0);
+        let color = film.pixel_color(4, 0);
+        assert!((color - Color::new(0.2, 0.4, 0.6)).length() < 1e-12, "{:?}", color);
+    }
+}
@@ -0,0 +1,142 @@
+use std::f64::consts::PI;
+
+// Pixel reconstruction filters. Every sample is splatted onto all pixels whose
+// center lies within `radius` of it, weighted by the filter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Box { radius: f64 },
+    Tent { radius: f64 },
+    Gaussian { radius: f64, alpha: f64 },
+    Mitchell { radius: f64, b: f64, c: f64 },
+    Lanczos { radius: f64, tau: f64 }
+}
+
+impl Filter {
+    // Builds a filter with sensible default parameters from its name
+    pub fn from_name(name: &str, radius: f64) -> Option<Filter> {
+        match name {
+            "box" => Some(Filter::Box { radius }),
+            "tent" => Some(Filter::Tent { radius }),
+            "gaussian" => Some(Filter::Gaussian { radius, alpha: 2.0 }),
+            "mitchell" => Some(Filter::Mitchell { radius, b: 1.0/3.0, c: 1.0/3.0 }),
+            "lanczos" => Some(Filter::Lanczos { radius, tau: radius }),
+            _ => None
+        }
+    }
+
+    // Radius used when none is given on the command line
+    pub fn default_radius(name: &str) -> f64 {
+        match name {
+            "box" => 0.5,
+            "tent" => 1.0,
+            _ => 2.0
+        }
+    }
+
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius, .. } => radius
+        }
+    }
+
+    // Weight of a sample at offset (x, y) from the pixel center. All filters are separable.
+    pub fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        match *self {
+            Filter::Box { radius } => if x <= radius { 1.0 } else { 0.0 },
+            Filter::Tent { radius } => f64::max(0.0, radius - x),
+            Filter::Gaussian { radius, alpha } => {
+                // Subtract the value at the radius so the filter goes smoothly to zero
+                f64::max(0.0, f64::exp(-alpha * x*x) - f64::exp(-alpha * radius*radius))
+            }
+            Filter::Mitchell { radius, b, c } => Filter::mitchell_1d(2.0 * x / radius, b, c),
+            Filter::Lanczos { radius, tau } => {
+                if x > radius {
+                    return 0.0;
+                }
+                // Sinc on the pixel offset, so its negative lobes fall between 1 and 2
+                // pixels, windowed by a sinc stretched over tau
+                Filter::sinc(x) * Filter::sinc(x / tau)
+            }
+        }
+    }
+
+    // Mitchell-Netravali cubic, defined over [-2, 2]
+    fn mitchell_1d(x: f64, b: f64, c: f64) -> f64 {
+        let x = x.abs();
+        if x > 2.0 {
+            return 0.0;
+        }
+        if x > 1.0 {
+            ((-b - 6.0*c) * x*x*x + (6.0*b + 30.0*c) * x*x
+                + (-12.0*b - 48.0*c) * x + (8.0*b + 24.0*c)) / 6.0
+        }
+        else {
+            ((12.0 - 9.0*b - 6.0*c) * x*x*x + (-18.0 + 12.0*b + 6.0*c) * x*x
+                + (6.0 - 2.0*b)) / 6.0
+        }
+    }
+
+    fn sinc(x: f64) -> f64 {
+        if x.abs() < 1e-5 {
+            return 1.0;
+        }
+        f64::sin(PI * x) / (PI * x)
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Filter;
+
+    #[test]
+    fn zero_outside_radius() {
+        for name in ["box", "tent", "gaussian", "mitchell", "lanczos"] {
+            let filter = Filter::from_name(name, 1.5).unwrap();
+
+            assert_eq!(filter.evaluate(1.6, 0.0), 0.0, "{}", name);
+            assert_eq!(filter.evaluate(0.0, -1.6), 0.0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn peak_at_center() {
+        for name in ["box", "tent", "gaussian", "mitchell", "lanczos"] {
+            let filter = Filter::from_name(name, 2.0).unwrap();
+
+            let center = filter.evaluate(0.0, 0.0);
+
+            assert!(center > 0.0, "{}", name);
+            assert!(center >= filter.evaluate(0.7, 0.3), "{}", name);
+        }
+    }
+
+    #[test]
+    fn mitchell_has_negative_lobe() {
+        let filter = Filter::from_name("mitchell", 2.0).unwrap();
+
+        assert!(filter.evaluate(1.5, 0.0) < 0.0);
+    }
+
+    #[test]
+    fn lanczos_has_negative_lobe() {
+        let filter = Filter::from_name("lanczos", 3.0).unwrap();
+
+        assert!(filter.evaluate(1.5, 0.0) < 0.0);
+        assert!(filter.evaluate(1.0, 0.0).abs() < 1e-9);
+    }
+}
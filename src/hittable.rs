@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use crate::{ray::Ray, vec3::{Point3, Vec3}, material::Material};
+use crate::{aabb::Aabb, ray::Ray, vec3::{Point3, Vec3}, material::Material};
 
 #[derive(Default)]
 pub struct HitRecord {
@@ -20,4 +20,6 @@ impl HitRecord {
 
 pub trait Hittable : Send + Sync {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool;
+    // False if the object is unbounded
+    fn bounding_box(&self, output_box: &mut Aabb) -> bool;
 }
\ No newline at end of file
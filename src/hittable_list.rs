@@ -1,6 +1,8 @@
+use crate::aabb::Aabb;
 use crate::hittable::Hittable;
 use crate::hittable::HitRecord;
 use crate::ray::Ray;
+use crate::stats::{self, Counter};
 pub struct HittableList {
     list: Vec<Box<dyn Hittable>>
 }
@@ -13,6 +15,7 @@ impl Hittable for HittableList {
         let mut closest_so_far = t_max;
 
         // iterate over list
+        stats::add(Counter::ObjectTests, self.list.len() as u64);
         for object in &self.list {
             if object.hit(r, t_min, closest_so_far, &mut temp_rec) {
                 hit_anything = true;
@@ -25,6 +28,23 @@ impl Hittable for HittableList {
         // return hit bool
         hit_anything
     }
+
+    fn bounding_box(&self, output_box: &mut Aabb) -> bool {
+        if self.list.is_empty() {
+            return false;
+        }
+
+        let mut temp_box = Aabb::default();
+        let mut first_box = true;
+        for object in &self.list {
+            if !object.bounding_box(&mut temp_box) {
+                return false;
+            }
+            *output_box = if first_box { temp_box } else { Aabb::surrounding_box(output_box, &temp_box) };
+            first_box = false;
+        }
+        true
+    }
 }
 
 impl HittableList {
@@ -0,0 +1,38 @@
+use crate::{util::degrees_to_radians, vec3::{Color, Vec3}};
+
+use super::LightSample;
+
+// Light from far away along one direction, like the sun. A non-zero angular
+// diameter spreads it over a disk of the sky, which softens the shadows.
+#[derive(Debug, Clone)]
+pub struct DirectionalLight {
+    // Unit vector toward the light, against the direction it shines in
+    pub to_light: Vec3,
+    // Irradiance on a surface facing the light
+    pub irradiance: Color,
+    cos_radius: f64
+}
+
+impl DirectionalLight {
+    // The angular diameter is in degrees
+    pub fn new(direction: Vec3, irradiance: Color, angle: f64) -> Result<DirectionalLight, String> {
+        if direction.near_zero() || !(0.0..180.0).contains(&angle) {
+            return Err(String::from("directional light needs a direction and an angle from 0 up to 180 degrees"));
+        }
+        Ok(DirectionalLight {
+            to_light: -direction.unit_vector(),
+            irradiance,
+            cos_radius: f64::cos(degrees_to_radians(angle / 2.0))
+        })
+    }
+
+    pub fn sample(&self, u: (f64, f64)) -> LightSample {
+        let direction = if self.cos_radius < 1.0 {
+            Vec3::random_in_cone(&self.to_light, self.cos_radius, u)
+        } else {
+            self.to_light
+        };
+        // A disk of even radiance sampled uniformly gives its irradiance every time
+        LightSample { direction, distance: f64::INFINITY, radiance: self.irradiance }
+    }
+}
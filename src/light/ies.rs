@@ -0,0 +1,233 @@
+use std::sync::Arc;
+
+use crate::{util::degrees_to_radians, vec3::Vec3};
+
+// Candela table of an IES LM-63 file, type C photometry: vertical angles from
+// the nadir (straight down the luminaire's axis, 0) to the zenith (180), and
+// horizontal angles around the axis, counterclockwise seen from above
+#[derive(Debug)]
+pub struct IesProfile {
+    vertical: Vec<f64>,
+    horizontal: Vec<f64>,
+    // One row of vertical angles per horizontal angle
+    candela: Vec<f64>
+}
+
+impl IesProfile {
+    pub fn load(path: &str) -> Result<IesProfile, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("cannot read IES file {}: {}", path, err))?;
+        IesProfile::parse(&text).map_err(|err| format!("{}: {}", path, err))
+    }
+
+    pub fn parse(text: &str) -> Result<IesProfile, String> {
+        // Keywords come first, the numbers after the TILT line can wrap anywhere
+        let mut lines = text.lines();
+        let tilt = lines.by_ref().find(|line| line.trim_start().starts_with("TILT=")).ok_or("no TILT line")?;
+        let mut numbers = lines.flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|word| !word.is_empty())
+            .map(|word| word.parse::<f64>().map_err(|_| format!("invalid number {}", word)));
+        let mut next = || numbers.next().unwrap_or(Err(String::from("file ends early")));
+
+        match tilt.trim() {
+            "TILT=NONE" => (),
+            "TILT=INCLUDE" => {
+                // Lamp to luminaire geometry, then angle and factor pairs
+                next()?;
+                let pairs = next()? as usize;
+                for _ in 0..2*pairs {
+                    next()?;
+                }
+            }
+            _ => return Err(String::from("only TILT=NONE or INCLUDE are supported"))
+        }
+
+        let _lamps = next()?;
+        let _lumens = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()?;
+        let _units = next()?;
+        for _ in 0..3 {
+            next()?; // Luminous opening
+        }
+        let ballast_factor = next()?;
+        for _ in 0..2 {
+            next()?; // Ballast-lamp factor and input watts
+        }
+
+        if photometric_type != 1.0 {
+            return Err(String::from("only type C photometry is supported"));
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(String::from("no candela values"));
+        }
+
+        let mut read = |n: usize| (0..n).map(|_| next()).collect::<Result<Vec<f64>, String>>();
+        let vertical = read(vertical_count)?;
+        let horizontal = read(horizontal_count)?;
+        let candela = read(vertical_count * horizontal_count)?.into_iter().map(|c| c * multiplier * ballast_factor).collect();
+
+        let increasing = |angles: &[f64]| angles.windows(2).all(|w| w[0] < w[1]);
+        if !increasing(&vertical) || !increasing(&horizontal) {
+            return Err(String::from("angles must be increasing"));
+        }
+        if ![0.0, 90.0, 180.0, 360.0].contains(&horizontal[horizontal_count - 1]) || horizontal[0] != 0.0 {
+            return Err(String::from("horizontal angles must start at 0 and end at 0, 90, 180 or 360"));
+        }
+        Ok(IesProfile { vertical, horizontal, candela })
+    }
+
+    // Candela in the direction at the angles, in degrees, interpolated from the table
+    pub fn candela(&self, vertical: f64, horizontal: f64) -> f64 {
+        // The last horizontal angle tells the symmetry the table leaves out
+        let last = self.horizontal[self.horizontal.len() - 1];
+        let mut horizontal = horizontal.rem_euclid(360.0);
+        if last <= 180.0 && horizontal > 180.0 {
+            horizontal = 360.0 - horizontal;
+        }
+        if last <= 90.0 && horizontal > 90.0 {
+            horizontal = 180.0 - horizontal;
+        }
+
+        let Some((v, tv)) = bracket(&self.vertical, vertical) else {
+            return 0.0;
+        };
+        let (h, th) = bracket(&self.horizontal, horizontal).unwrap_or((0, 0.0));
+        let row = |h: usize| {
+            let values = &self.candela[h * self.vertical.len()..];
+            let next = usize::min(v + 1, self.vertical.len() - 1);
+            (1.0 - tv) * values[v] + tv * values[next]
+        };
+        let next_h = usize::min(h + 1, self.horizontal.len() - 1);
+        (1.0 - th) * row(h) + th * row(next_h)
+    }
+}
+
+// Index of the interval holding x and how far into it x lies, None outside the angles
+fn bracket(angles: &[f64], x: f64) -> Option<(usize, f64)> {
+    if x < angles[0] || x > angles[angles.len() - 1] {
+        return None;
+    }
+    let i = angles.partition_point(|&a| a <= x).saturating_sub(1).min(angles.len() - 1);
+    if i + 1 >= angles.len() {
+        return Some((i, 0.0));
+    }
+    Some((i, (x - angles[i]) / (angles[i + 1] - angles[i])))
+}
+
+// A profile placed in the scene: where its nadir and its zero horizontal angle point
+#[derive(Debug, Clone)]
+pub struct Photometry {
+    profile: Arc<IesProfile>,
+    nadir: Vec3,
+    zero: Vec3,
+    ninety: Vec3
+}
+
+impl Photometry {
+    // Horizontal angle zero lies toward +x, or -z for a nadir along x, turned
+    // by `rotation` degrees about the nadir
+    pub fn new(profile: Arc<IesProfile>, nadir: Vec3, rotation: f64) -> Photometry {
+        let nadir = nadir.unit_vector();
+        let reference = if nadir.x.abs() > 0.9 { Vec3::new(0.0, 0.0, -1.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let zero = (reference - Vec3::dot(&reference, &nadir) * nadir).unit_vector();
+        let ninety = Vec3::cross(&zero, &nadir);
+        let rotation = degrees_to_radians(rotation);
+        Photometry {
+            profile,
+            nadir,
+            zero: f64::cos(rotation) * zero + f64::sin(rotation) * ninety,
+            ninety: f64::cos(rotation) * ninety - f64::sin(rotation) * zero
+        }
+    }
+
+    // Candela toward the unit direction leaving the light
+    pub fn candela(&self, direction: &Vec3) -> f64 {
+        let vertical = f64::acos(Vec3::dot(direction, &self.nadir).clamp(-1.0, 1.0)).to_degrees();
+        let horizontal = f64::atan2(Vec3::dot(direction, &self.ninety), Vec3::dot(direction, &self.zero)).to_degrees();
+        self.profile.candela(vertical, horizontal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{IesProfile, Photometry};
+    use crate::{
+        background::{Background, environment::EnvironmentMap},
+        hittable_list::HittableList,
+        light::{Light, point::PointLight, tree::LightTree},
+        material::{Material, lambertian::Lambertian},
+        ray::Ray,
+        render::ray_color,
+        sampler::{PixelSampler, Sampler},
+        scene::Scene,
+        sphere::Sphere,
+        vec3::{Color, Point3, Vec3}
+    };
+
+    // Bilateral symmetric fixture throwing more light across (90) than along (0) its length
+    const WALL_WASHER: &str = "IESNA:LM-63-2002
+[TEST] wall washer
+[MANUFAC] none
+TILT=NONE
+1 1000 2.0 4 3 1 2 0.1 0.1 0.0
+1.0 1.0 20
+0 30 60 90
+0 90 180
+100 80 40 0
+100 120 60
+10
+100 60 30 0
+";
+
+    #[test]
+    fn parse_and_interpolate() {
+        let profile = IesProfile::parse(WALL_WASHER).unwrap();
+        // Values are scaled by the multiplier
+        assert_eq!(profile.candela(0.0, 0.0), 200.0);
+        assert_eq!(profile.candela(30.0, 90.0), 240.0);
+        assert_eq!(profile.candela(45.0, 0.0), 120.0);
+        assert_eq!(profile.candela(30.0, 45.0), 200.0);
+        // Mirrored about the 0-180 plane, dark above the table
+        assert_eq!(profile.candela(60.0, 270.0), profile.candela(60.0, 90.0));
+        assert_eq!(profile.candela(120.0, 90.0), 0.0);
+    }
+
+    #[test]
+    fn rejects_other_photometry() {
+        assert!(IesProfile::parse(&WALL_WASHER.replace("4 3 1 2", "4 3 2 2")).is_err());
+        assert!(IesProfile::parse("IESNA:LM-63-2002\nTILT=NONE\n1 1000 1").is_err());
+    }
+
+    #[test]
+    fn ies_wall_wash_matches_the_candela_table() {
+        // Fixture 2 units in front of a wall, its nadir pointing at the wall
+        let white = Arc::new(Material::Lambertian(Lambertian::new(Color::new(1.0, 1.0, 1.0))));
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(Point3::new(0.0, 0.0, -2.0 - 1e5), 1e5, &white)));
+        let black = Background::Environment(Arc::new(EnvironmentMap::new(vec![Color::default(); 2], 2, 1, 0.0, 1.0)));
+        let nadir = Vec3::new(0.0, 0.0, -1.0);
+        let profile = Photometry::new(Arc::new(IesProfile::parse(WALL_WASHER).unwrap()), nadir, 0.0);
+        let scene = Scene { world, emitters: LightTree::default() };
+        let lights = [Light::Point(PointLight::new(Point3::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0)).with_profile(profile))];
+
+        // Horizontal angle 0 lies along +x and 90 along +y
+        for (vertical, horizontal, candela) in [(0.0, 0.0, 200.0), (30.0, 0.0, 160.0), (30.0, 90.0, 240.0), (60.0, 90.0, 120.0), (60.0, 180.0, 60.0)] {
+            let (theta, phi): (f64, f64) = (f64::to_radians(vertical), f64::to_radians(horizontal));
+            let direction = f64::cos(theta) * nadir + f64::sin(theta) * Vec3::new(f64::cos(phi), f64::sin(phi), 0.0);
+            let distance = 2.0 / f64::cos(theta);
+            let p = distance * direction;
+
+            let r = Ray::new(p + Vec3::new(0.0, 0.0, 0.5), nadir);
+            let mut sampler = PixelSampler::new(Sampler::Independent, 0, 0, 0, 1, 0, 1);
+            let radiance = ray_color(&r, &scene, &lights, &black, 1, &mut sampler).x;
+
+            // Lambertian wall: albedo / pi * candela * cos(incidence) / distance^2
+            let expected = candela * f64::cos(theta) / (distance * distance) / std::f64::consts::PI;
+            assert!((radiance - expected).abs() < 1e-3 * expected, "{} {}: {} != {}", vertical, horizontal, radiance, expected);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use crate::vec3::{Color, Point3, Vec3};
+
+use self::{directional::DirectionalLight, ies::{IesProfile, Photometry}, point::PointLight, spot::SpotLight};
+
+pub mod point;
+pub mod spot;
+pub mod directional;
+pub mod ies;
+pub mod tree;
+
+// Lights without a surface. Rays bouncing around the scene can never hit them,
+// they only light a point through shadow rays.
+#[derive(Debug, Clone)]
+pub enum Light {
+    Point(PointLight),
+    Spot(SpotLight),
+    Directional(DirectionalLight)
+}
+
+// Light arriving at a point from one direction
+pub struct LightSample {
+    // Unit vector toward the light
+    pub direction: Vec3,
+    // Distance to the light, infinite for directional lights
+    pub distance: f64,
+    // Radiance divided by the density of picking the direction
+    pub radiance: Color
+}
+
+impl Light {
+    pub fn sample(&self, p: &Point3, u: (f64, f64)) -> LightSample {
+        match self {
+            Light::Point(point) => point.sample(p),
+            Light::Spot(spot) => spot.sample(p),
+            Light::Directional(directional) => directional.sample(u)
+        }
+    }
+
+    // Parses a light given as its kind followed by key=value settings, like
+    // "spot position=0,4,0 target=0,0,0 intensity=20 inner=20 outer=30".
+    // Intensities are one number or r,g,b. Point and spot lights take an IES
+    // profile as ies=file, its candela are then scaled by the intensity. The
+    // profile's nadir points down a spot's axis or the aim of a point light
+    // (down by default), turned about it by rotation degrees.
+    pub fn parse(spec: &str) -> Result<Light, String> {
+        let mut words = spec.split_whitespace();
+        let kind = words.next().ok_or("empty --light")?;
+        let mut settings = Vec::new();
+        for word in words {
+            let (key, value) = word.split_once('=').ok_or(format!("invalid light setting {}, expected key=value", word))?;
+            settings.push((key, value));
+        }
+        let mut get = |key: &str| settings.iter().position(|&(k, _)| k == key).map(|i| settings.swap_remove(i).1);
+
+        let profile = get("ies").map(IesProfile::load).transpose()?.map(Arc::new);
+        let rotation = parse_number(get("rotation").unwrap_or("0"))?;
+
+        let light = match kind {
+            "point" => {
+                let light = PointLight::new(
+                    parse_vec3(get("position").ok_or("point light needs a position")?)?,
+                    parse_color(get("intensity").unwrap_or("1"))?
+                );
+                let aim = parse_vec3(get("aim").unwrap_or("0,-1,0"))?;
+                if aim.near_zero() {
+                    return Err(String::from("point light aim must not be zero"));
+                }
+                match profile {
+                    Some(profile) => Light::Point(light.with_profile(Photometry::new(profile, aim, rotation))),
+                    None => Light::Point(light)
+                }
+            }
+            "spot" => {
+                let light = SpotLight::new(
+                    parse_vec3(get("position").ok_or("spot light needs a position")?)?,
+                    parse_vec3(get("target").ok_or("spot light needs a target")?)?,
+                    parse_color(get("intensity").unwrap_or("1"))?,
+                    parse_number(get("inner").unwrap_or("30"))?,
+                    parse_number(get("outer").unwrap_or("45"))?
+                )?;
+                match profile {
+                    Some(profile) => {
+                        let axis = light.axis;
+                        Light::Spot(light.with_profile(Photometry::new(profile, axis, rotation)))
+                    }
+                    None => Light::Spot(light)
+                }
+            }
+            "directional" if profile.is_some() => return Err(String::from("directional lights don't take an IES profile")),
+            "directional" => Light::Directional(DirectionalLight::new(
+                parse_vec3(get("direction").ok_or("directional light needs a direction")?)?,
+                parse_color(get("intensity").unwrap_or("1"))?,
+                parse_number(get("angle").unwrap_or("0"))?
+            )?),
+            _ => return Err(format!("unknown light {}, expected point, spot or directional", kind))
+        };
+
+        match settings.first() {
+            Some((key, _)) => Err(format!("unknown setting {} for a {} light", key, kind)),
+            None => Ok(light)
+        }
+    }
+}
+
+fn parse_number(value: &str) -> Result<f64, String> {
+    value.parse().map_err(|_| format!("invalid number {} in --light", value))
+}
+
+fn parse_vec3(value: &str) -> Result<Vec3, String> {
+    let numbers: Vec<f64> = value.split(',').map(parse_number).collect::<Result<_, _>>()?;
+    match numbers[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(format!("invalid vector {} in --light, expected x,y,z", value))
+    }
+}
+
+fn parse_color(value: &str) -> Result<Color, String> {
+    if value.contains(',') {
+        parse_vec3(value)
+    } else {
+        let v = parse_number(value)?;
+        Ok(Color::new(v, v, v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Light;
+    use crate::vec3::{Color, Point3};
+
+    #[test]
+    fn parse_lights() {
+        let light = Light::parse("point position=0,4,0 intensity=8,4,2").unwrap();
+        let sample = light.sample(&Point3::new(0.0, 2.0, 0.0), (0.5, 0.5));
+        assert_eq!(sample.radiance, Color::new(2.0, 1.0, 0.5));
+        assert_eq!(sample.distance, 2.0);
+
+        assert!(Light::parse("spot position=0,4,0 target=0,0,0 inner=20 outer=10").is_err());
+        assert!(Light::parse("directional direction=0,-1,0 size=2").is_err());
+        assert!(Light::parse("area position=0,4,0").is_err());
+    }
+}
@@ -0,0 +1,72 @@
+use crate::vec3::{Color, Point3};
+
+use super::{LightSample, ies::Photometry};
+
+// Shines the same intensity in every direction from a point, or as much as
+// a photometric profile gives for the direction times the intensity
+#[derive(Debug, Clone)]
+pub struct PointLight {
+    pub position: Point3,
+    pub intensity: Color,
+    pub profile: Option<Photometry>
+}
+
+impl PointLight {
+    pub fn new(position: Point3, intensity: Color) -> PointLight {
+        PointLight { position, intensity, profile: None }
+    }
+
+    pub fn with_profile(self, profile: Photometry) -> PointLight {
+        PointLight { profile: Some(profile), ..self }
+    }
+
+    pub fn sample(&self, p: &Point3) -> LightSample {
+        let to_light = self.position - *p;
+        let distance = to_light.length();
+        let direction = to_light / distance;
+        let candela = self.profile.as_ref().map_or(1.0, |profile| profile.candela(&-direction));
+        LightSample {
+            direction,
+            distance,
+            radiance: candela * self.intensity / (distance * distance)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        background::{Background, environment::EnvironmentMap},
+        hittable_list::HittableList,
+        light::{Light, tree::LightTree},
+        material::{Material, lambertian::Lambertian},
+        ray::Ray,
+        render::ray_color,
+        sampler::{PixelSampler, Sampler},
+        scene::Scene,
+        sphere::Sphere,
+        vec3::{Color, Point3, Vec3}
+    };
+
+    #[test]
+    fn point_light_and_its_shadow() {
+        let gray = Arc::new(Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, &gray)));
+        let black = Background::Environment(Arc::new(EnvironmentMap::new(vec![Color::default(); 2], 2, 1, 0.0, 1.0)));
+        let lights = [Light::parse("point position=0,2,0 intensity=4").unwrap()];
+
+        // Straight below the light: albedo / pi * intensity / distance^2
+        let r = Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let mut sampler = PixelSampler::new(Sampler::Independent, 0, 0, 0, 1, 0, 1);
+        let mut scene = Scene { world, emitters: LightTree::default() };
+        let lit = ray_color(&r, &scene, &lights, &black, 8, &mut sampler);
+        assert!((lit.x - 0.5 / std::f64::consts::PI).abs() < 1e-9, "{:?}", lit);
+
+        scene.world.add(Box::new(Sphere::new(Point3::new(0.0, 1.5, 0.0), 0.2, &gray)));
+        let shadowed = ray_color(&r, &scene, &lights, &black, 8, &mut sampler);
+        assert_eq!(shadowed, Color::default());
+    }
+}
@@ -0,0 +1,76 @@
+use crate::{util::degrees_to_radians, vec3::{Color, Point3, Vec3}};
+
+use super::{LightSample, ies::Photometry};
+
+// Point light shining into a cone, at full intensity within the inner angle
+// and fading out smoothly toward the outer one. A photometric profile aimed
+// along the axis shapes the light within the cone.
+#[derive(Debug, Clone)]
+pub struct SpotLight {
+    pub position: Point3,
+    // Unit vector the light points along
+    pub axis: Vec3,
+    pub intensity: Color,
+    pub profile: Option<Photometry>,
+    cos_inner: f64,
+    cos_outer: f64
+}
+
+impl SpotLight {
+    // Angles are in degrees from the axis
+    pub fn new(position: Point3, target: Point3, intensity: Color, inner: f64, outer: f64) -> Result<SpotLight, String> {
+        if !(0.0 <= inner && inner <= outer && outer <= 180.0) || target == position {
+            return Err(String::from("spot light needs 0 <= inner <= outer <= 180 and a target away from it"));
+        }
+        Ok(SpotLight {
+            position,
+            axis: (target - position).unit_vector(),
+            intensity,
+            profile: None,
+            cos_inner: f64::cos(degrees_to_radians(inner)),
+            cos_outer: f64::cos(degrees_to_radians(outer))
+        })
+    }
+
+    pub fn with_profile(self, profile: Photometry) -> SpotLight {
+        SpotLight { profile: Some(profile), ..self }
+    }
+
+    pub fn sample(&self, p: &Point3) -> LightSample {
+        let to_light = self.position - *p;
+        let distance = to_light.length();
+        let direction = to_light / distance;
+        let candela = self.profile.as_ref().map_or(1.0, |profile| profile.candela(&-direction));
+        LightSample {
+            direction,
+            distance,
+            radiance: candela * self.falloff(Vec3::dot(&-direction, &self.axis)) * self.intensity / (distance * distance)
+        }
+    }
+
+    // Smoothstep between the cones
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta >= self.cos_inner {
+            return 1.0;
+        }
+        let t = ((cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpotLight;
+    use crate::vec3::{Color, Point3};
+
+    #[test]
+    fn cone_falloff() {
+        let spot = SpotLight::new(Point3::new(0.0, 1.0, 0.0), Point3::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0), 30.0, 60.0).unwrap();
+        let at = |x: f64| spot.sample(&Point3::new(x, 0.0, 0.0)).radiance.x * (1.0 + x * x);
+
+        // Full inside 30 degrees, fading at 45 and dark beyond 60
+        assert!((at(0.5) - 1.0).abs() < 1e-12);
+        assert!(at(1.0) > 0.2 && at(1.0) < 0.8);
+        assert_eq!(at(2.0), 0.0);
+    }
+}
@@ -0,0 +1,292 @@
+use std::f64::consts::PI;
+
+use crate::{aabb::Aabb, material::Material, sphere::Sphere, vec3::{Color, Point3, Vec3}};
+
+use super::LightSample;
+
+// A glowing sphere as the light tree sees it
+#[derive(Debug, Clone)]
+struct SphereLight {
+    center: Point3,
+    radius: f64,
+    emit: Color
+}
+
+impl SphereLight {
+    // Density per solid angle of the cone of directions the sphere covers seen from p,
+    // zero from inside
+    fn cone_pdf(&self, p: &Point3) -> f64 {
+        let distance_squared = (self.center - *p).length_squared();
+        let sin_squared = self.radius * self.radius / distance_squared;
+        if sin_squared >= 1.0 {
+            return 0.0;
+        }
+        // 1 - cos without losing small cones to cancellation
+        let one_minus_cos = sin_squared / (1.0 + f64::sqrt(1.0 - sin_squared));
+        1.0 / (2.0 * PI * one_minus_cos)
+    }
+}
+
+#[derive(Debug)]
+enum NodeKind {
+    Leaf(usize),
+    Interior(usize, usize)
+}
+
+#[derive(Debug)]
+struct Node {
+    bounds: Aabb,
+    // Total emitted power below the node
+    power: f64,
+    kind: NodeKind
+}
+
+// Bounding volume hierarchy over the emissive spheres of a scene, each node
+// knowing the power below it. A light is picked by walking down from the
+// root, taking each child in proportion to its estimated contribution at the
+// shading point: its power over the squared distance to it.
+#[derive(Debug, Default)]
+pub struct LightTree {
+    lights: Vec<SphereLight>,
+    nodes: Vec<Node>
+}
+
+impl LightTree {
+    pub fn new(spheres: &[Sphere]) -> LightTree {
+        let lights: Vec<SphereLight> = spheres.iter().filter_map(|sphere| match sphere.material.as_ref() {
+            Material::DiffuseLight(light) if light.emit.luminance() > 0.0 => {
+                Some(SphereLight { center: sphere.center, radius: sphere.radius, emit: light.emit })
+            }
+            _ => None
+        }).collect();
+
+        let mut tree = LightTree { lights, nodes: Vec::new() };
+        if !tree.lights.is_empty() {
+            let mut indices: Vec<usize> = (0..tree.lights.len()).collect();
+            tree.build(&mut indices);
+        }
+        tree
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    // Direction from p toward a point on a light picked by the tree, and its density per solid angle
+    pub fn sample(&self, p: &Point3, u_select: f64, u: (f64, f64)) -> Option<(LightSample, f64)> {
+        if self.is_empty() {
+            return None;
+        }
+
+        // Reuse the sample at every level, rescaled to the part the chosen child got
+        let (mut node, mut pmf, mut u_select) = (0, 1.0, u_select);
+        let light = loop {
+            match self.nodes[node].kind {
+                NodeKind::Leaf(light) => break &self.lights[light],
+                NodeKind::Interior(left, right) => {
+                    let p_left = self.left_probability(left, right, p)?;
+                    if u_select < p_left {
+                        u_select /= p_left;
+                        pmf *= p_left;
+                        node = left;
+                    } else {
+                        u_select = (u_select - p_left) / (1.0 - p_left);
+                        pmf *= 1.0 - p_left;
+                        node = right;
+                    }
+                }
+            }
+        };
+
+        let cone_pdf = light.cone_pdf(p);
+        if cone_pdf == 0.0 {
+            return None;
+        }
+        let to_center = light.center - *p;
+        let distance_squared = to_center.length_squared();
+        let cos_max = f64::sqrt(1.0 - light.radius * light.radius / distance_squared);
+        let direction = Vec3::random_in_cone(&(to_center / distance_squared.sqrt()), cos_max, u);
+
+        // Nearest point of the sphere along the direction
+        let b = Vec3::dot(&direction, &to_center);
+        let discriminant = f64::max(0.0, b * b - distance_squared + light.radius * light.radius);
+        let sample = LightSample { direction, distance: b - discriminant.sqrt(), radiance: light.emit };
+        Some((sample, pmf * cone_pdf))
+    }
+
+    // Density per solid angle with which `sample` from p finds the point q on a light
+    pub fn pdf(&self, p: &Point3, q: &Point3) -> f64 {
+        if self.is_empty() { 0.0 } else { self.node_pdf(0, p, q) }
+    }
+
+    fn node_pdf(&self, node: usize, p: &Point3, q: &Point3) -> f64 {
+        match self.nodes[node].kind {
+            NodeKind::Leaf(light) => {
+                let light = &self.lights[light];
+                let on_surface = ((*q - light.center).length() - light.radius).abs() < 1e-6 * f64::max(1.0, light.radius);
+                if on_surface { light.cone_pdf(p) } else { 0.0 }
+            }
+            NodeKind::Interior(left, right) => {
+                let Some(p_left) = self.left_probability(left, right, p) else {
+                    return 0.0;
+                };
+                let mut pdf = 0.0;
+                for (child, probability) in [(left, p_left), (right, 1.0 - p_left)] {
+                    if probability > 0.0 && contains(&self.nodes[child].bounds, q) {
+                        pdf += probability * self.node_pdf(child, p, q);
+                    }
+                }
+                pdf
+            }
+        }
+    }
+
+    // Chance of going to the left child at p, None if neither matters
+    fn left_probability(&self, left: usize, right: usize, p: &Point3) -> Option<f64> {
+        let (left, right) = (self.importance(left, p), self.importance(right, p));
+        if left + right > 0.0 { Some(left / (left + right)) } else { None }
+    }
+
+    fn importance(&self, node: usize, p: &Point3) -> f64 {
+        let node = &self.nodes[node];
+        let center = 0.5 * (node.bounds.minimum + node.bounds.maximum);
+        // Points inside the box are as close as its size allows
+        let half_diagonal_squared = 0.25 * (node.bounds.maximum - node.bounds.minimum).length_squared();
+        node.power / f64::max((center - *p).length_squared(), half_diagonal_squared)
+    }
+
+    // Adds the subtree over the lights, splitting them in half along the
+    // longest extent of their centers. Returns the index of its root.
+    fn build(&mut self, indices: &mut [usize]) -> usize {
+        let node = self.nodes.len();
+        let bounds = indices.iter().map(|&i| self.light_bounds(i)).reduce(|a, b| Aabb::surrounding_box(&a, &b)).unwrap();
+        let power = indices.iter().map(|&i| {
+            let light = &self.lights[i];
+            light.emit.luminance() * PI * 4.0 * PI * light.radius * light.radius
+        }).sum();
+        self.nodes.push(Node { bounds, power, kind: NodeKind::Leaf(indices[0]) });
+        if indices.len() == 1 {
+            return node;
+        }
+
+        let centers = indices.iter().map(|&i| Aabb::new(self.lights[i].center, self.lights[i].center))
+            .reduce(|a, b| Aabb::surrounding_box(&a, &b)).unwrap();
+        let extent = centers.maximum - centers.minimum;
+        let axis = if extent.x > extent.y && extent.x > extent.z { 0 } else if extent.y > extent.z { 1 } else { 2 };
+        let coordinate = |i: &usize| {
+            let center = self.lights[*i].center;
+            [center.x, center.y, center.z][axis]
+        };
+        indices.sort_by(|a, b| coordinate(a).total_cmp(&coordinate(b)));
+
+        let (left, right) = indices.split_at_mut(indices.len() / 2);
+        let left = self.build(left);
+        let right = self.build(right);
+        self.nodes[node].kind = NodeKind::Interior(left, right);
+        node
+    }
+
+    fn light_bounds(&self, i: usize) -> Aabb {
+        let light = &self.lights[i];
+        let extent = Vec3::new(light.radius, light.radius, light.radius);
+        Aabb::new(light.center - extent, light.center + extent)
+    }
+}
+
+// Whether the point is in the box, give or take rounding
+fn contains(bounds: &Aabb, p: &Point3) -> bool {
+    let epsilon = 1e-6 * f64::max(1.0, (bounds.maximum - bounds.minimum).length());
+    p.x >= bounds.minimum.x - epsilon && p.x <= bounds.maximum.x + epsilon
+        && p.y >= bounds.minimum.y - epsilon && p.y <= bounds.maximum.y + epsilon
+        && p.z >= bounds.minimum.z - epsilon && p.z <= bounds.maximum.z + epsilon
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::LightTree;
+    use crate::{
+        background::{Background, environment::EnvironmentMap}, hittable_list::HittableList,
+        material::{Material, diffuse_light::DiffuseLight, lambertian::Lambertian}, ray::Ray, render::ray_color,
+        sampler::{PixelSampler, Sampler}, scene::Scene, sphere::Sphere, vec3::{Color, Point3, Vec3}
+    };
+
+    fn street() -> Vec<Sphere> {
+        let lamp = Arc::new(Material::DiffuseLight(DiffuseLight::new(Color::new(4.0, 4.0, 4.0))));
+        let gray = Arc::new(Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let mut spheres = vec![Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, &gray)];
+        for i in 0..50 {
+            spheres.push(Sphere::new(Point3::new(i as f64 * 2.0, 3.0, 0.0), 0.1, &lamp));
+        }
+        spheres
+    }
+
+    #[test]
+    fn only_emitters_are_lights() {
+        let tree = LightTree::new(&street());
+        assert_eq!(tree.lights.len(), 50);
+        assert!(LightTree::new(&street()[..1]).is_empty());
+    }
+
+    #[test]
+    fn near_lights_are_picked_more_and_pdfs_match() {
+        let tree = LightTree::new(&street());
+        let p = Point3::new(20.0, 0.0, 0.0);
+        let mut near = 0;
+        for i in 0..1000 {
+            let (sample, pdf) = tree.sample(&p, (i as f64 + 0.5) / 1000.0, (0.3, 0.6)).unwrap();
+            let q = p + sample.distance * sample.direction;
+            assert!((tree.pdf(&p, &q) - pdf).abs() < 1e-9 * pdf, "{} != {}", tree.pdf(&p, &q), pdf);
+            if (q.x - 20.0).abs() < 3.0 {
+                near += 1;
+            }
+        }
+        // 3 of the 50 lamps are within 3 units
+        assert!(near > 300, "{}", near);
+    }
+
+    #[test]
+    fn selection_probabilities_sum_to_one() {
+        let tree = LightTree::new(&street());
+        let p = Point3::new(7.0, 1.0, 2.0);
+        // Each lamp's pdf at its point facing p, over its cone pdf, is its chance of being picked
+        let total: f64 = tree.lights.iter().map(|light| {
+            let q = light.center + light.radius * (p - light.center).unit_vector();
+            tree.pdf(&p, &q) / light.cone_pdf(&p)
+        }).sum();
+        assert!((total - 1.0).abs() < 1e-9, "{}", total);
+    }
+
+    #[test]
+    fn glowing_sphere_lights_the_floor() {
+        // A sphere of radiance 1 seen under the angle alpha gives an irradiance of pi * sin^2(alpha)
+        let gray = Arc::new(Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let glow = Arc::new(Material::DiffuseLight(DiffuseLight::new(Color::new(1.0, 1.0, 1.0))));
+        let spheres = vec![
+            Sphere::new(Point3::new(0.0, -1e4, 0.0), 1e4, &gray),
+            Sphere::new(Point3::new(0.0, 2.0, 0.0), 0.5, &glow)
+        ];
+        let black = Background::Environment(Arc::new(EnvironmentMap::new(vec![Color::default(); 2], 2, 1, 0.0, 1.0)));
+        let expected = 0.5 * (0.5f64 / 2.0).powi(2);
+
+        // With the lights sampled through the tree, and found only by bouncing
+        for emitters in [LightTree::new(&spheres), LightTree::default()] {
+            let mut world = HittableList::new();
+            for sphere in &spheres {
+                world.add(Box::new(Sphere::new(sphere.center, sphere.radius, &sphere.material)));
+            }
+            let scene = Scene { world, emitters };
+
+            let samples = 20000;
+            let mut sum = 0.0;
+            for index in 0..samples {
+                let mut sampler = PixelSampler::new(Sampler::Independent, 5, 0, 0, 1, index, samples);
+                let r = Ray::new(Point3::new(0.0, 0.5, 0.3), Vec3::new(0.0, -0.5, -0.3));
+                sum += ray_color(&r, &scene, &[], &black, 4, &mut sampler).x;
+            }
+            let mean = sum / samples as f64;
+            assert!((mean - expected).abs() < 0.03 * expected, "{} != {}", mean, expected);
+        }
+    }
+}
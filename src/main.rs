@@ -1,151 +1,284 @@
+mod aabb;
+mod animation;
+mod background;
+mod distribution;
 mod vec3;
 mod ray;
 mod hittable_list;
 mod hittable;
+mod light;
 mod sphere;
 mod camera;
 mod util;
 mod material;
+mod filter;
+mod film;
+mod options;
+mod render;
+mod checkpoint;
+mod bytes;
+mod distributed;
+mod tile;
+mod stats;
+mod progress;
+mod rng;
+mod sampler;
+mod scene;
+#[cfg(test)]
+mod test_scene;
 
 
-use std::f64::INFINITY;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use hittable::HitRecord;
+use aabb::Aabb;
 use hittable::Hittable;
 use hittable_list::HittableList;
-use ray::Ray;
-use rayon::prelude::*;
 use vec3::{Point3, Color};
 use sphere::Sphere;
 use material::Material;
 
-use crate::camera::Camera;
+use crate::camera::{Camera, CameraSettings};
+use crate::material::conductor::Conductor;
 use crate::material::dielectric::Dielectric;
+use crate::material::diffuse_light::DiffuseLight;
 use crate::material::lambertian::Lambertian;
 use crate::material::metal::Metal;
-use crate::util::random_double;
+use crate::light::tree::LightTree;
+use crate::options::Options;
+use crate::rng::Rng;
+use crate::scene::Scene;
 use crate::vec3::Vec3;
 
 
 fn main() {
     // Image
-    const ASPECT_RATIO: f64 = 3.0 / 2.0;
-    const WIDTH: u32 = 1200;
-    const HEIGHT: u32 = (WIDTH as f64 / ASPECT_RATIO) as u32;
-    const SAMPLES_PER_PIXEL: u32 = 50;
-    const MAX_DEPTH: u32 = 50;
-   
-    let mut pixels = vec![];
-    for y in (0..HEIGHT).rev() {
-        for x in 0..WIDTH {
-            pixels.push((y as f64, x as f64));
+    let options = Options::from_args(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(2);
+    });
+
+    // Worker mode: render tiles for a coordinator
+    if let Some(address) = &options.serve {
+        if let Err(err) = distributed::serve(address, build_scene, Duration::from_secs_f64(options.worker_timeout)) {
+            eprintln!("Worker failed: {}", err);
+            std::process::exit(1);
         }
+        return;
     }
 
-    // World
-    let world = random_scene();
+    // A single image, or every frame of the sequence
+    let frames = options.frames.clone().unwrap_or(options.frame..=options.frame);
+    let start = Instant::now();
+    for frame in frames {
+        render_frame(&options.for_frame(frame));
+    }
+    println!("{}", stats::Report::collect(start.elapsed(), !options.workers.is_empty()));
+}
 
-    // Camera
-    let look_from = Point3::new(13.0, 2.0, 3.0);
-    let look_at = Point3::new(0.0, 0.0, 0.0);
-    let vup = Vec3::new(0.0, 1.0, 0.0);
-    let dist_to_focus = 10.0;
-    let aperture = 0.1;
+fn render_frame(options: &Options) {
+    let (scene, cam) = build_scene(options).unwrap_or_else(|err| {
+        eprintln!("Cannot set up the scene: {}", err);
+        std::process::exit(1);
+    });
+
+    // The crop window is pasted into the earlier render, so it has to match the full image
+    if let Some(base) = &options.composite {
+        match image::image_dimensions(base) {
+            Ok(dimensions) if dimensions == (options.width, options.height) => {}
+            Ok((w, h)) => {
+                eprintln!("Cannot composite into {}: it is {}x{}, the render is {}x{}", base, w, h, options.width, options.height);
+                std::process::exit(1);
+            }
+            Err(err) => {
+                eprintln!("Cannot composite into {}: {}", base, err);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    let cam = Camera::new(look_from, look_at, vup, 20.0, ASPECT_RATIO, aperture, dist_to_focus);
+    // Continue an interrupted render
+    let resume = match &options.checkpoint {
+        Some(path) if options.resume => match checkpoint::load(path, options) {
+            Ok(checkpoint) => Some(checkpoint),
+            // Frames of a sequence that hadn't been started yet
+            Err(err) if options.frames.is_some() && err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => {
+                eprintln!("Failed to resume from {}: {}", path, err);
+                std::process::exit(1);
+            }
+        },
+        _ => None
+    };
 
     let start = Instant::now();
     // Render
-    let bytes: Vec<u8> = pixels.par_iter().flat_map(|(y, x)| {
-        let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-
-        for _ in 0..SAMPLES_PER_PIXEL {
-            let u = (x + random_double(0.0, 1.0)) / (WIDTH-1) as f64;
-            let v = (y + random_double(0.0, 1.0)) / (HEIGHT-1) as f64;
-            let r = cam.get_ray(u, v);
-            pixel_color += ray_color(&r, &world, MAX_DEPTH);
-        }
-
-        let c = pixel_color.translate(SAMPLES_PER_PIXEL);
-        vec![c.x as u8, c.y as u8, c.z as u8]
-    }).collect();
+    let film = render::render(&scene, &cam, options, resume);
 
     // Print how long it took to render
-    let duration = start.elapsed().as_secs();
-    println!("Render took: {} seconds", duration);
+    let duration = start.elapsed();
+    match options.frames {
+        Some(_) => println!("Frame {} took: {} seconds", options.frame, duration.as_secs()),
+        None => println!("Render took: {} seconds", duration.as_secs())
+    }
     
     // Save image
-    image::save_buffer("render.png", &bytes, WIDTH, HEIGHT, image::ColorType::Rgb8).unwrap();
+    render::save_output(&film, options);
+    if let Some(heatmap) = &options.heatmap {
+        film.save_heatmap(heatmap, options.crop.unwrap_or(film.region()), options.max_samples());
+    }
 }
 
-fn ray_color(r: &Ray, world: &HittableList, depth: u32) -> Color {
-    if depth == 0 {
-        return Color::new(0.0, 0.0, 0.0);
+// World and camera. Workers of a distributed render call this with the same
+// options as the coordinator, so it must only depend on them.
+fn build_scene(options: &Options) -> Result<(Scene, Camera), String> {
+    // World
+    let mut objects = random_scene(&mut Rng::new(options.seed, 0), options.emissive_spheres);
+
+    // Camera
+    let mut camera = CameraSettings {
+        projection: options.projection,
+        look_from: Point3::new(13.0, 2.0, 3.0),
+        look_at: Point3::new(0.0, 0.0, 0.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        vfov: 20.0,
+        aperture: 0.1,
+        focus_dist: 10.0,
+        bokeh: options.bokeh.clone(),
+        shift: options.shift,
+        tilt: options.tilt,
+        lens: options.lens.clone(),
+        stereo: options.stereo
+    };
+
+    // Move everything to where it is in this frame
+    if let Some(animation) = &options.animation {
+        animation.animate_objects(&mut objects, options.frame as f64)?;
+        camera = animation.camera(camera, options.frame as f64);
     }
 
-    let mut rec = HitRecord::default();
+    // Metal and glass as the command line has them
+    for object in &mut objects {
+        match object.material.as_ref() {
+            Material::Metal(metal) => if let Some(model) = options.metal {
+                object.material = Arc::new(Material::Conductor(Conductor::from_metal(metal, model, options.anisotropy)));
+            }
+            Material::Dielectric(dielectric) => {
+                let glass = dielectric.clone().with_roughness(options.glass_roughness).with_fresnel(options.fresnel);
+                object.material = Arc::new(Material::Dielectric(glass));
+            }
+            _ => ()
+        }
+    }
+
+    // A real lens decides the field of view and the depth of field, its
+    // field of view changes as it focuses
+    let apply_physical = |camera: &mut CameraSettings| if let Some(physical) = &options.physical {
+        camera.vfov = physical.vfov(options.aspect_ratio, camera.focus_dist);
+        camera.aperture = physical.aperture();
+    };
+    apply_physical(&mut camera);
 
-    if world.hit(r, 0.001, INFINITY, &mut rec) {
-        let mut scattered = Ray::default();
-        let mut attenuation = Color::default();
-        if rec.material.scatter(r, &rec, &mut attenuation, &mut scattered) {
-            return attenuation * ray_color(&scattered, world, depth-1);
+    if let Some(indices) = &options.framing {
+        if let Some(bounds) = bounds_of(&objects, indices)? {
+            camera.frame(&bounds, options.aspect_ratio);
+            apply_physical(&mut camera);
         }
-        return Color::new(0.0, 0.0, 0.0);
     }
 
-    let unit_direction = r.direction().unit_vector();
-    let t = 0.5*(unit_direction.y + 1.0);
-    (1.0-t)*Color::new(1.0, 1.0, 1.0) + t*Color::new(0.5, 0.7, 1.0)
+    // Glowing objects are also sampled as lights
+    let emitters = LightTree::new(&objects);
+    let mut world = HittableList::new();
+    for object in objects {
+        world.add(Box::new(object));
+    }
+
+    if let Some(target) = options.autofocus {
+        camera.focus_dist = camera.auto_focus(&world, target, options.width, options.height, options.aspect_ratio);
+        apply_physical(&mut camera);
+    }
+    Ok((Scene { world, emitters }, camera.build(options.aspect_ratio)?))
 }
 
-fn random_scene() -> HittableList {
-    let mut world = HittableList::new();
+// Box around the objects at these indices, negative ones count from the end
+// and no indices means all objects. None if there is nothing to bound, an
+// error for indices past either end.
+fn bounds_of(objects: &[Sphere], indices: &[i64]) -> Result<Option<Aabb>, String> {
+    let selected: Vec<&Sphere> = if indices.is_empty() {
+        objects.iter().collect()
+    } else {
+        indices.iter()
+            .map(|&index| {
+                let from_start = if index < 0 { objects.len() as i64 + index } else { index };
+                usize::try_from(from_start).ok().and_then(|i| objects.get(i))
+                    .ok_or(format!("--frame-objects index {} is out of range, the scene has {} objects", index, objects.len()))
+            })
+            .collect::<Result<_, _>>()?
+    };
+
+    let mut bounds: Option<Aabb> = None;
+    for object in selected {
+        let mut object_box = Aabb::default();
+        if object.bounding_box(&mut object_box) {
+            bounds = Some(bounds.map_or(object_box, |bounds| Aabb::surrounding_box(&bounds, &object_box)));
+        }
+    }
+    Ok(bounds)
+}
+
+// The book's final scene. `emissive` is the fraction of small diffuse spheres
+// turned into lights, none keeps the scene as it always was.
+fn random_scene(rng: &mut Rng, emissive: f64) -> Vec<Sphere> {
+    let mut world = Vec::new();
 
     let ground_material = Arc::new(Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
-    world.add(Box::new(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, &ground_material)));
+    world.push(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, &ground_material));
 
 
     for a in -11..11 {
         for b in -11..11 {
-            let choose_mat = random_double(0.0, 1.0);
-            let center = Point3::new(a as f64 + 0.9*random_double(0.0, 1.0), 0.2, b as f64 + 0.9*random_double(0.0, 1.0));
+            let choose_mat = rng.random_double(0.0, 1.0);
+            let center = Point3::new(a as f64 + 0.9*rng.random_double(0.0, 1.0), 0.2, b as f64 + 0.9*rng.random_double(0.0, 1.0));
 
             if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
                 let sphere_material;
 
-                if choose_mat < 0.8 {
+                if choose_mat < 0.8 && emissive > 0.0 && rng.random_double(0.0, 1.0) < emissive {
+                    // light
+                    let emit = 4.0 * Color::random(rng, 0.5, 1.0);
+                    sphere_material = Arc::new(Material::DiffuseLight(DiffuseLight::new(emit)));
+                    world.push(Sphere::new(center, 0.2, &sphere_material))
+                }
+                else if choose_mat < 0.8 {
                     // diffuse
-                    let albedo = Color::random(0.0, 1.0) * Color::random(0.0, 1.0);
+                    let albedo = Color::random(rng, 0.0, 1.0) * Color::random(rng, 0.0, 1.0);
                     sphere_material = Arc::new(Material::Lambertian(Lambertian::new(albedo)));
-                    world.add(Box::new(Sphere::new(center, 0.2, &sphere_material)))
+                    world.push(Sphere::new(center, 0.2, &sphere_material))
                 }
                 else if choose_mat < 0.95 {
                     // metal
-                    let albedo = Color::random(0.5, 1.0);
-                    let fuzz = random_double(0.0, 0.5);
+                    let albedo = Color::random(rng, 0.5, 1.0);
+                    let fuzz = rng.random_double(0.0, 0.5);
                     sphere_material = Arc::new(Material::Metal(Metal::new(albedo, fuzz)));
-                    world.add(Box::new(Sphere::new(center, 0.2, &sphere_material)))
+                    world.push(Sphere::new(center, 0.2, &sphere_material))
                 }
                 else {
                     // glass
                     sphere_material = Arc::new(Material::Dielectric(Dielectric::new(1.5)));
-                    world.add(Box::new(Sphere::new(center, 0.2, &sphere_material)))
+                    world.push(Sphere::new(center, 0.2, &sphere_material))
                 }
             }
         }
     }
 
     let material1 = Arc::new(Material::Dielectric(Dielectric::new(1.5)));
-    world.add(Box::new(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, &material1)));
+    world.push(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, &material1));
 
     let material2 = Arc::new(Material::Lambertian(Lambertian::new(Color::new(0.4, 0.2, 0.1))));
-    world.add(Box::new(Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, &material2)));
+    world.push(Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, &material2));
 
     let material3 = Arc::new(Material::Metal(Metal::new(Color::new(0.7, 0.6, 0.5), 0.1)));
-    world.add(Box::new(Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, &material3)));
+    world.push(Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, &material3));
 
     world
 }
\ No newline at end of file
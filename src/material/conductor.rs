@@ -0,0 +1,242 @@
+use crate::{vec3::{Color, Vec3}, ray::Ray, hittable::HitRecord, sampler::PixelSampler};
+
+use super::{metal::Metal, microfacet::{Frame, Ggx}};
+
+// Complex index of refraction n + ik of a metal, at a red, green and blue wavelength
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ComplexIor {
+    pub eta: Color,
+    pub k: Color
+}
+
+impl ComplexIor {
+    pub fn preset(name: &str) -> Option<ComplexIor> {
+        let (eta, k) = match name {
+            "gold" => (Color::new(0.143, 0.374, 1.442), Color::new(3.983, 2.385, 1.603)),
+            "copper" => (Color::new(0.200, 0.924, 1.102), Color::new(3.912, 2.452, 2.142)),
+            "aluminium" => (Color::new(1.657, 0.880, 0.521), Color::new(9.224, 6.270, 4.837)),
+            "silver" => (Color::new(0.155, 0.117, 0.138), Color::new(4.828, 3.122, 2.147)),
+            _ => return None
+        };
+        Some(ComplexIor { eta, k })
+    }
+
+    // The metal that reflects `reflectivity` head on and turns toward
+    // `edge_tint` at grazing angles (Gulbrandsen 2014)
+    pub fn from_reflectivity(reflectivity: Color, edge_tint: Color) -> ComplexIor {
+        let channel = |r: f64, g: f64| {
+            let r = r.clamp(0.0, 0.99);
+            let eta = g * (1.0 - r) / (1.0 + r) + (1.0 - g) * (1.0 + r.sqrt()) / (1.0 - r.sqrt());
+            let k = f64::sqrt(f64::max(0.0, (r * (eta + 1.0).powi(2) - (eta - 1.0).powi(2)) / (1.0 - r)));
+            (eta, k)
+        };
+        let (r, g, b) = (
+            channel(reflectivity.x, edge_tint.x),
+            channel(reflectivity.y, edge_tint.y),
+            channel(reflectivity.z, edge_tint.z)
+        );
+        ComplexIor { eta: Color::new(r.0, g.0, b.0), k: Color::new(r.1, g.1, b.1) }
+    }
+
+    // Unpolarized Fresnel reflectance from air
+    pub fn fresnel(&self, cos_theta: f64) -> Color {
+        Color::new(
+            fresnel_conductor(cos_theta, self.eta.x, self.k.x),
+            fresnel_conductor(cos_theta, self.eta.y, self.k.y),
+            fresnel_conductor(cos_theta, self.eta.z, self.k.z)
+        )
+    }
+}
+
+fn fresnel_conductor(cos_theta: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_theta.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let t0 = eta*eta - k*k - sin2;
+    let a2b2 = f64::sqrt(t0*t0 + 4.0*eta*eta*k*k);
+    let t1 = a2b2 + cos2;
+    let a = f64::sqrt(f64::max(0.0, 0.5 * (a2b2 + t0)));
+    let t2 = 2.0 * cos_theta * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2*a2b2 + sin2*sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+}
+
+// How the fuzzy metals of the scene become conductors: tinted by their albedo
+// or made of a measured metal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetalModel {
+    Tinted,
+    Ior(ComplexIor)
+}
+
+impl MetalModel {
+    pub fn parse(name: &str) -> Option<MetalModel> {
+        match name {
+            "ggx" => Some(MetalModel::Tinted),
+            _ => ComplexIor::preset(name).map(MetalModel::Ior)
+        }
+    }
+}
+
+// Rough metal with a GGX distribution of microfacet normals. Anisotropy
+// stretches the highlights along the tangent, which runs around the world's
+// vertical axis.
+#[derive(Clone)]
+pub struct Conductor {
+    pub ior: ComplexIor,
+    pub roughness: f64,
+    pub anisotropy: f64,
+    ggx: Ggx
+}
+
+impl Conductor {
+    pub fn new(ior: ComplexIor, roughness: f64, anisotropy: f64) -> Conductor {
+        Conductor { ior, roughness, anisotropy, ggx: Ggx::new(roughness, anisotropy) }
+    }
+
+    // The conductor standing in for a fuzzy metal, as rough as it is fuzzy
+    pub fn from_metal(metal: &Metal, model: MetalModel, anisotropy: f64) -> Conductor {
+        let ior = match model {
+            MetalModel::Tinted => ComplexIor::from_reflectivity(metal.albedo, metal.albedo),
+            MetalModel::Ior(ior) => ior
+        };
+        Conductor::new(ior, metal.fuzz.clamp(0.0, 1.0), anisotropy)
+    }
+
+    pub fn is_specular(&self) -> bool {
+        self.ggx.is_smooth()
+    }
+
+    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray, sampler: &mut PixelSampler) -> bool {
+        let frame = Frame::new(&rec.normal);
+        let wo = frame.to_local(&-r_in.direction().unit_vector());
+        if wo.z <= 0.0 {
+            return false;
+        }
+
+        if self.is_specular() {
+            *scattered = Ray::new(rec.p, frame.to_world(&Vec3::new(-wo.x, -wo.y, wo.z)));
+            *attenuation = self.ior.fresnel(wo.z);
+            return true;
+        }
+
+        let h = self.ggx.sample_visible_normal(&wo, sampler.get_2d());
+        let wi = Vec3::reflect(&-wo, &h);
+        if wi.z <= 0.0 {
+            return false;
+        }
+        // f * cos / pdf, with the distribution cancelling out
+        *attenuation = self.ior.fresnel(Vec3::dot(&wo, &h)) * (self.ggx.masking_shadowing(&wo, &wi) / self.ggx.masking(&wo));
+        *scattered = Ray::new(rec.p, frame.to_world(&wi));
+        true
+    }
+
+    pub fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        let Some((wo, wi)) = self.local_directions(r_in, rec, wi) else {
+            return Color::default();
+        };
+        let h = (wo + wi).unit_vector();
+        self.ior.fresnel(Vec3::dot(&wo, &h)) * (self.ggx.distribution(&h) * self.ggx.masking_shadowing(&wo, &wi) / (4.0 * wo.z))
+    }
+
+    // Density of reflecting off visible normals
+    pub fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        let Some((wo, wi)) = self.local_directions(r_in, rec, wi) else {
+            return 0.0;
+        };
+        let h = (wo + wi).unit_vector();
+        self.ggx.visible_pdf(&wo, &h) / (4.0 * Vec3::dot(&wo, &h))
+    }
+
+    // wo and wi in the shading frame, None unless both are above a rough surface
+    fn local_directions(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Option<(Vec3, Vec3)> {
+        if self.is_specular() {
+            return None;
+        }
+        let frame = Frame::new(&rec.normal);
+        let wo = frame.to_local(&-r_in.direction().unit_vector());
+        let wi = frame.to_local(&wi.unit_vector());
+        (wo.z > 0.0 && wi.z > 0.0).then_some((wo, wi))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::{ComplexIor, Conductor};
+    use crate::{hittable::HitRecord, ray::Ray, sampler::{PixelSampler, Sampler}, vec3::{Color, Point3, Vec3}};
+
+    fn hit() -> HitRecord {
+        HitRecord { normal: Vec3::new(0.0, 0.0, 1.0), front_face: true, ..HitRecord::default() }
+    }
+
+    // Integral over the hemisphere of f(wo, wi) cos(wi), and of the pdf
+    fn integrate(conductor: &Conductor, r_in: &Ray) -> (Color, f64) {
+        let n = 400;
+        let (mut reflected, mut pdf) = (Color::default(), 0.0);
+        for i in 0..n {
+            for j in 0..n {
+                // Uniform over the hemisphere
+                let z = (i as f64 + 0.5) / n as f64;
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n as f64;
+                let r = f64::sqrt(1.0 - z*z);
+                let wi = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+                reflected += conductor.eval(r_in, &hit(), &wi);
+                pdf += conductor.pdf(r_in, &hit(), &wi);
+            }
+        }
+        let solid_angle = 2.0 * PI / (n * n) as f64;
+        (solid_angle * reflected, solid_angle * pdf)
+    }
+
+    #[test]
+    fn presets_have_their_color() {
+        let gold = ComplexIor::preset("gold").unwrap().fresnel(1.0);
+        assert!(gold.x > gold.y && gold.y > gold.z);
+        let aluminium = ComplexIor::preset("aluminium").unwrap().fresnel(1.0);
+        assert!(aluminium.x > 0.85 && aluminium.z > 0.85);
+        // Every metal is a mirror at grazing angles
+        assert!((ComplexIor::preset("copper").unwrap().fresnel(0.0).z - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn reflectivity_round_trips() {
+        let color = Color::new(0.9, 0.6, 0.3);
+        let f0 = ComplexIor::from_reflectivity(color, color).fresnel(1.0);
+        assert!((f0 - color).length() < 1e-9, "{:?}", f0);
+    }
+
+    #[test]
+    fn sampling_matches_evaluation() {
+        let mut perfect = ComplexIor::preset("silver").unwrap();
+        perfect.k = Color::new(1e6, 1e6, 1e6);
+        let r_in = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(-0.6, 0.2, -0.7));
+
+        for (roughness, anisotropy, least) in [(0.3, 0.0, 0.95), (0.6, 0.8, 0.6)] {
+            let conductor = Conductor::new(perfect, roughness, anisotropy);
+            let (reflected, pdf) = integrate(&conductor, &r_in);
+            // What doesn't reflect once is lost in the microfacets, more the rougher
+            // they are, never more than comes in
+            assert!(reflected.x <= 1.0 && reflected.x > least, "{:?}", reflected);
+
+            // The estimator of the scattered rays has the same mean, and the pdf the
+            // rays that leave the surface integrate to
+            let samples = 20000;
+            let (mut estimate, mut leaving) = (0.0, 0);
+            for index in 0..samples {
+                let mut sampler = PixelSampler::new(Sampler::Independent, 2, 0, 0, 1, index, samples);
+                let (mut attenuation, mut scattered) = (Color::default(), Ray::default());
+                if conductor.scatter(&r_in, &hit(), &mut attenuation, &mut scattered, &mut sampler) {
+                    estimate += attenuation.x;
+                    leaving += 1;
+                }
+            }
+            let estimate = estimate / samples as f64;
+            assert!((estimate - reflected.x).abs() < 0.01, "{} != {}", estimate, reflected.x);
+            assert!((leaving as f64 / samples as f64 - pdf).abs() < 0.01, "{} != {}", leaving, pdf);
+        }
+    }
+}
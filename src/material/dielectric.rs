@@ -1,22 +1,80 @@
-use crate::{hittable::HitRecord, ray::Ray, vec3::{Color, Vec3}, util::random_double};
+use crate::{hittable::HitRecord, ray::Ray, vec3::{Color, Vec3}, sampler::PixelSampler};
 
+use super::microfacet::{Frame, Ggx};
+
+// How much light a dielectric boundary reflects
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Fresnel {
+    #[default]
+    Schlick,
+    Exact
+}
+
+impl Fresnel {
+    pub fn parse(name: &str) -> Option<Fresnel> {
+        match name {
+            "schlick" => Some(Fresnel::Schlick),
+            "exact" => Some(Fresnel::Exact),
+            _ => None
+        }
+    }
+
+    // Reflected fraction for light arriving at cos_i, going from index n_i to
+    // n_t with ratio n_i / n_t. Everything is reflected past the critical angle.
+    pub fn reflectance(&self, cos_i: f64, ratio: f64) -> f64 {
+        let cos_i = cos_i.clamp(0.0, 1.0);
+        let sin_t_squared = ratio * ratio * (1.0 - cos_i * cos_i);
+        if sin_t_squared >= 1.0 {
+            return 1.0;
+        }
+        let cos_t = f64::sqrt(1.0 - sin_t_squared);
+
+        match self {
+            // The book's approximation, on the incident angle from either side
+            Fresnel::Schlick => {
+                let r0 = ((1.0 - ratio) / (1.0 + ratio)).powi(2);
+                r0 + (1.0 - r0) * f64::powi(1.0 - cos_i, 5)
+            }
+            Fresnel::Exact => {
+                let rs = (ratio * cos_i - cos_t) / (ratio * cos_i + cos_t);
+                let rp = (cos_i - ratio * cos_t) / (cos_i + ratio * cos_t);
+                0.5 * (rs * rs + rp * rp)
+            }
+        }
+    }
+}
+
+// Glass, smooth or frosted by a GGX distribution of microfacets that reflect
+// and refract (Walter et al. 2007). Like the smooth one, the rough one keeps
+// radiance as is across the boundary.
+#[derive(Clone)]
 pub struct Dielectric {
-    ir: f64 // Index of refraction
+    pub ir: f64, // Index of refraction
+    pub roughness: f64,
+    pub fresnel: Fresnel
 }
 
 impl Dielectric {
-    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool {
+    pub fn is_specular(&self) -> bool {
+        self.ggx().is_smooth()
+    }
+
+    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray, sampler: &mut PixelSampler) -> bool {
         *attenuation = Color::new(1.0, 1.0, 1.0);
         let refraction_ratio = if rec.front_face { 1.0/self.ir } else { self.ir };
 
+        if !self.is_specular() {
+            return self.scatter_rough(r_in, rec, refraction_ratio, attenuation, scattered, sampler);
+        }
+
         let unit_direction = r_in.direction().unit_vector();
         let cos_theta = f64::min(Vec3::dot(&-unit_direction, &rec.normal), 1.0);
         let sin_theta = f64::sqrt(1.0 - cos_theta*cos_theta);
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let direction = if cannot_refract || Dielectric::reflectance(cos_theta, refraction_ratio) > random_double(0.0, 1.0) {
+        let direction = if cannot_refract || self.fresnel.reflectance(cos_theta, refraction_ratio) > sampler.get_1d() {
             Vec3::reflect(&unit_direction, &rec.normal)
-        } 
+        }
         else {
             Vec3::refract(&unit_direction, &rec.normal, refraction_ratio)
         };
@@ -25,17 +83,184 @@ impl Dielectric {
         true
     }
 
-    fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
-        // Use Schlick's approximation for reflectance.
-        let mut r0 = (1.0-ref_idx) / (1.0+ref_idx);
-        r0 = r0*r0;
+    // Picks a facet wo sees, then reflects or refracts through it as its Fresnel term says
+    fn scatter_rough(&self, r_in: &Ray, rec: &HitRecord, refraction_ratio: f64, attenuation: &mut Color, scattered: &mut Ray, sampler: &mut PixelSampler) -> bool {
+        let ggx = self.ggx();
+        let frame = Frame::new(&rec.normal);
+        let wo = frame.to_local(&-r_in.direction().unit_vector());
+        if wo.z <= 0.0 {
+            return false;
+        }
+
+        let h = ggx.sample_visible_normal(&wo, sampler.get_2d());
+        let reflectance = self.fresnel.reflectance(Vec3::dot(&wo, &h), refraction_ratio);
+        let wi = if reflectance > sampler.get_1d() {
+            Vec3::reflect(&-wo, &h)
+        } else {
+            Vec3::refract(&-wo, &h, refraction_ratio)
+        };
+        // Reflections must leave the surface, refractions go through it
+        let reflected = Vec3::dot(&wi, &h) > 0.0;
+        if (wi.z > 0.0) != reflected {
+            return false;
+        }
+
+        // f * cos / pdf, everything but the masking cancels out
+        *attenuation = Color::new(1.0, 1.0, 1.0) * (ggx.masking_shadowing(&wo, &wi) / ggx.masking(&wo));
+        *scattered = Ray::new(rec.p, frame.to_world(&wi));
+        true
+    }
+
+    // Scattered fraction of light arriving from wi, on either side, times its cosine
+    pub fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        let Some(facet) = self.facet(r_in, rec, wi) else {
+            return Color::default();
+        };
+        let ggx = self.ggx();
+        let f = ggx.distribution(&facet.h) * ggx.masking_shadowing(&facet.wo, &facet.wi) * facet.fresnel * facet.jacobian / facet.wo.z;
+        Color::new(f, f, f)
+    }
+
+    // Density with which scatter picks wi
+    pub fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        let Some(facet) = self.facet(r_in, rec, wi) else {
+            return 0.0;
+        };
+        self.ggx().visible_pdf(&facet.wo, &facet.h) * facet.fresnel * facet.jacobian / Vec3::dot(&facet.wo, &facet.h)
+    }
+
+    // The facet that scatters wo into wi, None if the glass is smooth or no facet does
+    fn facet(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Option<Facet> {
+        if self.is_specular() {
+            return None;
+        }
+        let frame = Frame::new(&rec.normal);
+        let wo = frame.to_local(&-r_in.direction().unit_vector());
+        let wi = frame.to_local(&wi.unit_vector());
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return None;
+        }
+        // n_t / n_i
+        let eta = if rec.front_face { self.ir } else { 1.0 / self.ir };
+
+        if wi.z > 0.0 {
+            let h = (wo + wi).unit_vector();
+            let fresnel = self.fresnel.reflectance(Vec3::dot(&wo, &h), 1.0 / eta);
+            return Some(Facet { wo, wi, h, fresnel, jacobian: 0.25 });
+        }
 
-        r0 + (1.0-r0)*f64::powi(1.0 - cosine, 5)
+        // The generalized half vector, facing wo
+        let mut h = (wo + eta * wi).unit_vector();
+        if h.z < 0.0 {
+            h = -h;
+        }
+        let (cos_o, cos_i) = (Vec3::dot(&wo, &h), Vec3::dot(&wi, &h));
+        if cos_o <= 0.0 || cos_i >= 0.0 {
+            return None;
+        }
+        let fresnel = 1.0 - self.fresnel.reflectance(cos_o, 1.0 / eta);
+        let denominator = (cos_o + eta * cos_i).powi(2);
+        Some(Facet { wo, wi, h, fresnel, jacobian: eta * eta * cos_o * -cos_i / denominator })
+    }
+
+    fn ggx(&self) -> Ggx {
+        Ggx::new(self.roughness, 0.0)
     }
 }
 
+// wo, wi and the facet normal between them in the shading frame, the fraction
+// of light the facet sends that way, and the change from facet normals to
+// directions wi times wo's cosine on the facet
+struct Facet {
+    wo: Vec3,
+    wi: Vec3,
+    h: Vec3,
+    fresnel: f64,
+    jacobian: f64
+}
+
 impl Dielectric {
     pub fn new(ir: f64) -> Dielectric {
-        Dielectric { ir } 
+        Dielectric { ir, roughness: 0.0, fresnel: Fresnel::default() }
+    }
+
+    pub fn with_roughness(mut self, roughness: f64) -> Dielectric {
+        self.roughness = roughness;
+        self
+    }
+
+    pub fn with_fresnel(mut self, fresnel: Fresnel) -> Dielectric {
+        self.fresnel = fresnel;
+        self
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::{Dielectric, Fresnel};
+    use crate::{hittable::HitRecord, ray::Ray, sampler::{PixelSampler, Sampler}, vec3::{Color, Point3, Vec3}};
+
+    #[test]
+    fn fresnel_terms_agree() {
+        for fresnel in [Fresnel::Schlick, Fresnel::Exact] {
+            assert!((fresnel.reflectance(1.0, 1.0 / 1.5) - 0.04).abs() < 1e-9);
+            // Past the critical angle inside the glass
+            assert_eq!(fresnel.reflectance(0.5, 1.5), 1.0);
+        }
+        // Light crossing either way at matching angles reflects alike
+        let cos_i = 0.8;
+        let cos_t = f64::sqrt(1.0 - (1.0 - cos_i * cos_i) / (1.5 * 1.5));
+        assert!((Fresnel::Exact.reflectance(cos_i, 1.0 / 1.5) - Fresnel::Exact.reflectance(cos_t, 1.5)).abs() < 1e-9);
+        let (schlick, exact) = (Fresnel::Schlick.reflectance(0.3, 1.0 / 1.5), Fresnel::Exact.reflectance(0.3, 1.0 / 1.5));
+        assert!((schlick - exact).abs() < 0.01, "{} {}", schlick, exact);
+    }
+
+    #[test]
+    fn rough_sampling_matches_evaluation_from_both_sides() {
+        let glass = Dielectric::new(1.5).with_roughness(0.4).with_fresnel(Fresnel::Exact);
+        let r_in = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(-0.5, 0.1, -0.8));
+
+        for front_face in [true, false] {
+            let rec = HitRecord { normal: Vec3::new(0.0, 0.0, 1.0), front_face, ..HitRecord::default() };
+
+            // Integral over the sphere of directions of f(wo, wi) cos(wi), and of the pdf
+            let n = 600;
+            let (mut scattered, mut pdf) = (0.0, 0.0);
+            for i in 0..n {
+                for j in 0..n {
+                    let z = -1.0 + 2.0 * (i as f64 + 0.5) / n as f64;
+                    let phi = 2.0 * PI * (j as f64 + 0.5) / n as f64;
+                    let r = f64::sqrt(1.0 - z*z);
+                    let wi = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+                    scattered += glass.eval(&r_in, &rec, &wi).x;
+                    pdf += glass.pdf(&r_in, &rec, &wi);
+                }
+            }
+            let solid_angle = 4.0 * PI / (n * n) as f64;
+            let (scattered, pdf) = (solid_angle * scattered, solid_angle * pdf);
+
+            let samples = 20000;
+            let (mut estimate, mut leaving, mut through) = (0.0, 0, 0);
+            for index in 0..samples {
+                let mut sampler = PixelSampler::new(Sampler::Independent, 3, 0, 0, 1, index, samples);
+                let (mut attenuation, mut ray) = (Color::default(), Ray::default());
+                if glass.scatter(&r_in, &rec, &mut attenuation, &mut ray, &mut sampler) {
+                    estimate += attenuation.x;
+                    leaving += 1;
+                    if ray.direction().z < 0.0 {
+                        through += 1;
+                    }
+                }
+            }
+            let estimate = estimate / samples as f64;
+            assert!(scattered <= 1.0, "{}", scattered);
+            assert!((estimate - scattered).abs() < 0.01, "{} != {}", estimate, scattered);
+            assert!((leaving as f64 / samples as f64 - pdf).abs() < 0.01, "{} != {}", leaving, pdf);
+            // More goes through into the glass than out of it, where much is reflected back
+            let transmitted = through as f64 / samples as f64;
+            assert!(if front_face { transmitted > 0.85 } else { transmitted < 0.85 }, "{}", transmitted);
+        }
+    }
+}
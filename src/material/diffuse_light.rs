@@ -0,0 +1,19 @@
+use crate::{vec3::Color, hittable::HitRecord};
+
+// Glows evenly from its front side, and reflects nothing
+#[derive(Clone)]
+pub struct DiffuseLight {
+    pub emit: Color
+}
+
+impl DiffuseLight {
+    pub fn emitted(&self, rec: &HitRecord) -> Color {
+        if rec.front_face { self.emit } else { Color::default() }
+    }
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> DiffuseLight {
+        DiffuseLight { emit }
+    }
+}
@@ -1,12 +1,15 @@
-use crate::{vec3::{Color, Vec3}, hittable::HitRecord, ray::Ray};
+use std::f64::consts::PI;
 
+use crate::{vec3::{Color, Vec3}, hittable::HitRecord, ray::Ray, sampler::PixelSampler};
+
+#[derive(Clone)]
 pub struct Lambertian {
-    albedo: Color
+    pub albedo: Color
 }
 
 impl Lambertian {
-    pub fn scatter(&self, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool {
-        let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
+    pub fn scatter(&self, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray, sampler: &mut PixelSampler) -> bool {
+        let mut scatter_direction = rec.normal + Vec3::random_unit_vector(sampler.get_2d());
         
         // Catch degenerate scatter direction
         if scatter_direction.near_zero() {
@@ -17,6 +20,15 @@ impl Lambertian {
         *attenuation = self.albedo;
         true
     }
+
+    pub fn eval(&self, rec: &HitRecord, wi: &Vec3) -> Color {
+        self.albedo * self.pdf(rec, wi)
+    }
+
+    // Scattering is cosine weighted
+    pub fn pdf(&self, rec: &HitRecord, wi: &Vec3) -> f64 {
+        f64::max(Vec3::dot(&rec.normal, &wi.unit_vector()), 0.0) / PI
+    }
 }
 
 impl Lambertian {
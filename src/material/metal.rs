@@ -1,14 +1,15 @@
-use crate::{vec3::{Color, Vec3}, ray::Ray, hittable::HitRecord};
+use crate::{vec3::{Color, Vec3}, ray::Ray, hittable::HitRecord, sampler::PixelSampler};
 
+#[derive(Clone)]
 pub struct Metal {
-    albedo: Color,
-    fuzz: f64
+    pub albedo: Color,
+    pub fuzz: f64
 }
 
 impl Metal {
-    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool {
+    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray, sampler: &mut PixelSampler) -> bool {
         let reflected = Vec3::reflect(&r_in.direction().unit_vector(), &rec.normal);
-        *scattered = Ray::new(rec.p, reflected + self.fuzz*Vec3::random_in_unit_sphere());
+        *scattered = Ray::new(rec.p, reflected + self.fuzz*Vec3::random_in_unit_sphere(sampler.get_2d(), sampler.get_1d()));
         *attenuation = self.albedo;
         
         Vec3::dot(&scattered.direction(), &rec.normal) > 0.0
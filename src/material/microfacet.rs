@@ -0,0 +1,96 @@
+use std::f64::consts::PI;
+
+use crate::vec3::Vec3;
+
+// GGX (Trowbridge-Reitz) distribution of microfacet normals, in a shading
+// frame with the macro normal as z. Wider along x than y when anisotropic.
+#[derive(Debug, Clone, Copy)]
+pub struct Ggx {
+    alpha_x: f64,
+    alpha_y: f64
+}
+
+// Below this the surface is a mirror
+const SMOOTH: f64 = 1e-3;
+
+impl Ggx {
+    // Perceptual roughness in [0, 1] squared gives the distribution's width,
+    // anisotropy in [0, 1) narrows it across x
+    pub fn new(roughness: f64, anisotropy: f64) -> Ggx {
+        let aspect = f64::sqrt(1.0 - 0.9 * anisotropy);
+        let alpha = roughness * roughness;
+        Ggx { alpha_x: alpha / aspect, alpha_y: alpha * aspect }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        f64::max(self.alpha_x, self.alpha_y) < SMOOTH
+    }
+
+    pub fn distribution(&self, h: &Vec3) -> f64 {
+        let (ax, ay) = (self.alpha_x, self.alpha_y);
+        let t = (h.x / ax).powi(2) + (h.y / ay).powi(2) + h.z * h.z;
+        1.0 / (PI * ax * ay * t * t)
+    }
+
+    // Smith's masking, G1 = 1 / (1 + lambda)
+    pub fn lambda(&self, w: &Vec3) -> f64 {
+        let tan2 = ((self.alpha_x * w.x).powi(2) + (self.alpha_y * w.y).powi(2)) / (w.z * w.z);
+        0.5 * (-1.0 + f64::sqrt(1.0 + tan2))
+    }
+
+    pub fn masking(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Of the facets wo sees, the fraction wi sees too
+    pub fn masking_shadowing(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Density of the normals wo sees, wo above the surface
+    pub fn visible_pdf(&self, wo: &Vec3, h: &Vec3) -> f64 {
+        self.masking(wo) * self.distribution(h) * f64::max(0.0, Vec3::dot(wo, h)) / wo.z
+    }
+
+    // Normal of a microfacet wo sees, in proportion to how much of it wo sees (Heitz 2018)
+    pub fn sample_visible_normal(&self, wo: &Vec3, u: (f64, f64)) -> Vec3 {
+        // Stretch to the hemisphere configuration
+        let vh = Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).unit_vector();
+        let length_squared = vh.x*vh.x + vh.y*vh.y;
+        let t1 = if length_squared > 0.0 { Vec3::new(-vh.y, vh.x, 0.0) / length_squared.sqrt() } else { Vec3::new(1.0, 0.0, 0.0) };
+        let t2 = Vec3::cross(&vh, &t1);
+
+        // Uniform disk, squashed toward the part of the hemisphere wo sees
+        let r = u.0.sqrt();
+        let phi = 2.0 * PI * u.1;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * f64::sqrt(1.0 - p1*p1) + s * r * phi.sin();
+        let nh = p1*t1 + p2*t2 + f64::sqrt(f64::max(0.0, 1.0 - p1*p1 - p2*p2))*vh;
+
+        Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, f64::max(0.0, nh.z)).unit_vector()
+    }
+}
+
+// Shading frame: the tangent around the vertical axis, the bitangent and the normal as z
+pub struct Frame {
+    tangent: Vec3,
+    bitangent: Vec3,
+    normal: Vec3
+}
+
+impl Frame {
+    pub fn new(normal: &Vec3) -> Frame {
+        let around = Vec3::cross(&Vec3::new(0.0, 1.0, 0.0), normal);
+        let tangent = if around.near_zero() { normal.orthonormal_basis().0 } else { around.unit_vector() };
+        Frame { tangent, bitangent: Vec3::cross(normal, &tangent), normal: *normal }
+    }
+
+    pub fn to_local(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(Vec3::dot(v, &self.tangent), Vec3::dot(v, &self.bitangent), Vec3::dot(v, &self.normal))
+    }
+
+    pub fn to_world(&self, v: &Vec3) -> Vec3 {
+        v.x*self.tangent + v.y*self.bitangent + v.z*self.normal
+    }
+}
@@ -1,23 +1,86 @@
-use crate::{hittable::HitRecord, vec3::{Color, Vec3}, ray::Ray};
+use crate::{hittable::HitRecord, vec3::{Color, Vec3}, ray::Ray, sampler::PixelSampler};
 
-use self::{metal::Metal, lambertian::Lambertian, dielectric::Dielectric};
+use self::{metal::Metal, lambertian::Lambertian, dielectric::Dielectric, diffuse_light::DiffuseLight, conductor::Conductor};
 
 pub mod lambertian;
 pub mod metal;
 pub mod dielectric;
+pub mod diffuse_light;
+pub mod conductor;
+pub mod microfacet;
 
+#[derive(Clone)]
 pub enum Material {
     Lambertian(Lambertian),
     Metal(Metal),
-    Dielectric(Dielectric)
+    Dielectric(Dielectric),
+    DiffuseLight(DiffuseLight),
+    Conductor(Conductor)
 }
 
 impl Material {
-    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool {
+    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray, sampler: &mut PixelSampler) -> bool {
         match self {
-            Material::Lambertian(lambertian) => lambertian.scatter(rec, attenuation, scattered),
-            Material::Metal(metal) => metal.scatter(r_in, rec, attenuation, scattered),
-            Material::Dielectric(dielectric) => dielectric.scatter(r_in, rec, attenuation, scattered)
+            Material::Lambertian(lambertian) => lambertian.scatter(rec, attenuation, scattered, sampler),
+            Material::Metal(metal) => metal.scatter(r_in, rec, attenuation, scattered, sampler),
+            Material::Dielectric(dielectric) => dielectric.scatter(r_in, rec, attenuation, scattered, sampler),
+            Material::DiffuseLight(_) => false,
+            Material::Conductor(conductor) => conductor.scatter(r_in, rec, attenuation, scattered, sampler)
+        }
+    }
+
+    // Radiance the surface gives off by itself
+    pub fn emitted(&self, rec: &HitRecord) -> Color {
+        match self {
+            Material::DiffuseLight(light) => light.emitted(rec),
+            _ => Color::default()
+        }
+    }
+
+    // Mirror-like materials scatter into directions too narrow to be found
+    // by sampling lights, so they are only lit by the rays they scatter
+    pub fn is_specular(&self) -> bool {
+        match self {
+            Material::Lambertian(_) => false,
+            // Fuzzy reflection is narrow enough to be treated as a mirror
+            Material::Metal(_) => true,
+            Material::Dielectric(dielectric) => dielectric.is_specular(),
+            // Nothing to light
+            Material::DiffuseLight(_) => true,
+            Material::Conductor(conductor) => conductor.is_specular()
+        }
+    }
+
+    // Reflected fraction of light arriving from direction wi toward where r_in
+    // came from, times its cosine
+    pub fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        match self {
+            Material::Lambertian(lambertian) => lambertian.eval(rec, wi),
+            Material::Conductor(conductor) => conductor.eval(r_in, rec, wi),
+            Material::Dielectric(dielectric) => dielectric.eval(r_in, rec, wi),
+            Material::Metal(_) | Material::DiffuseLight(_) => Color::default()
+        }
+    }
+
+    // Density per solid angle with which scatter picks direction wi
+    pub fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        match self {
+            Material::Lambertian(lambertian) => lambertian.pdf(rec, wi),
+            Material::Conductor(conductor) => conductor.pdf(r_in, rec, wi),
+            Material::Dielectric(dielectric) => dielectric.pdf(r_in, rec, wi),
+            Material::Metal(_) | Material::DiffuseLight(_) => 0.0
+        }
+    }
+
+    // Copy with the given parameters replaced, ones this kind of material doesn't have are ignored
+    pub fn with_parameters(&self, albedo: Option<Color>, fuzz: Option<f64>, ir: Option<f64>) -> Material {
+        match self {
+            Material::Lambertian(lambertian) => Material::Lambertian(Lambertian::new(albedo.unwrap_or(lambertian.albedo))),
+            Material::Metal(metal) => Material::Metal(Metal::new(albedo.unwrap_or(metal.albedo), fuzz.unwrap_or(metal.fuzz))),
+            Material::Dielectric(dielectric) => Material::Dielectric(Dielectric { ir: ir.unwrap_or(dielectric.ir), ..dielectric.clone() }),
+            Material::DiffuseLight(light) => Material::DiffuseLight(light.clone()),
+            // Fuzz is how rough the metal is
+            Material::Conductor(conductor) => Material::Conductor(Conductor::new(conductor.ior, fuzz.unwrap_or(conductor.roughness), conductor.anisotropy))
         }
     }
 }
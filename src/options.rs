@@ -0,0 +1,680 @@
+use std::{ops::RangeInclusive, sync::Arc};
+
+use crate::{
+    animation::Animation,
+    background::{Background, environment::EnvironmentMap, sky::{self, Sky}},
+    camera::{FocusTarget, Projection, aperture::{ApertureShape, Bokeh}, physical::PhysicalCamera, realistic::Lens, stereo::{StereoLayout, StereoSettings}},
+    filter::Filter,
+    light::Light,
+    material::{conductor::MetalModel, dielectric::Fresnel},
+    sampler::Sampler,
+    tile::{Region, TileOrder},
+    util::degrees_to_radians
+};
+
+// Render settings, overridable from the command line
+#[derive(Clone)]
+pub struct Options {
+    pub aspect_ratio: f64,
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_pixel: u32,
+    pub min_samples_per_pixel: u32,
+    pub noise_threshold: f64,
+    // Pixels that haven't converged may take up to this many samples, as long
+    // as the image as a whole takes no more than samples_per_pixel on average
+    pub max_samples_per_pixel: Option<u32>,
+    pub pass_samples: u32,
+    pub preview_interval: f64,
+    pub max_depth: u32,
+    // Fraction of the small diffuse spheres that glow
+    pub emissive_spheres: f64,
+    // GGX conductors in place of the fuzzy metals, and how anisotropic they are
+    pub metal: Option<MetalModel>,
+    pub anisotropy: f64,
+    // Frosted glass, and how all glass reflects
+    pub glass_roughness: f64,
+    pub fresnel: Fresnel,
+    pub background: Background,
+    // Point, spot and directional lights
+    pub lights: Vec<Light>,
+    pub projection: Projection,
+    pub stereo: Option<StereoSettings>,
+    pub bokeh: Bokeh,
+    pub shift: (f64, f64),
+    pub tilt: (f64, f64),
+    // Set the focus distance from what the camera sees
+    pub autofocus: Option<FocusTarget>,
+    // Point the camera at these objects, negative indices count from the end and
+    // an empty list frames everything
+    pub framing: Option<Vec<i64>>,
+    // Derive the field of view and aperture from real camera settings
+    pub physical: Option<PhysicalCamera>,
+    // Lens prescription to trace rays through, with the sensor size and scene scale of `physical`
+    pub lens: Option<Lens>,
+    // Factor on every sample, from the physical camera's f-stop, shutter and ISO
+    // when any of them is given
+    pub exposure: f64,
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    pub crop: Option<Region>,
+    pub composite: Option<String>,
+    pub filter: Filter,
+    pub sampler: Sampler,
+    pub seed: u64,
+    pub output: String,
+    pub heatmap: Option<String>,
+    pub checkpoint: Option<String>,
+    pub checkpoint_interval: f64,
+    pub resume: bool,
+    // Passes to render before stopping, to finish later from the checkpoint
+    pub stop_after_passes: Option<u32>,
+    pub workers: Vec<String>,
+    // Seconds to wait on a worker before giving its tiles to the others, and on
+    // a worker, on its coordinator before hanging up
+    pub worker_timeout: f64,
+    pub serve: Option<String>,
+    pub animation: Option<Animation>,
+    // Frame to render, and the frames of a sequence
+    pub frame: u32,
+    pub frames: Option<RangeInclusive<u32>>,
+    // The arguments these options were parsed from
+    pub args: Vec<String>
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        let aspect_ratio = 3.0 / 2.0;
+        let width = 1200;
+
+        Options {
+            aspect_ratio,
+            width,
+            height: (width as f64 / aspect_ratio) as u32,
+            samples_per_pixel: 50,
+            min_samples_per_pixel: 8,
+            noise_threshold: 0.0,
+            max_samples_per_pixel: None,
+            pass_samples: 8,
+            preview_interval: 30.0,
+            max_depth: 50,
+            emissive_spheres: 0.0,
+            metal: None,
+            anisotropy: 0.0,
+            glass_roughness: 0.0,
+            fresnel: Fresnel::default(),
+            background: Background::default(),
+            lights: Vec::new(),
+            projection: Projection::default(),
+            stereo: None,
+            bokeh: Bokeh::default(),
+            shift: (0.0, 0.0),
+            tilt: (0.0, 0.0),
+            autofocus: None,
+            framing: None,
+            physical: None,
+            lens: None,
+            exposure: 1.0,
+            tile_size: 32,
+            tile_order: TileOrder::default(),
+            crop: None,
+            composite: None,
+            filter: Filter::default(),
+            sampler: Sampler::default(),
+            seed: 0,
+            output: String::from("render.png"),
+            heatmap: None,
+            checkpoint: None,
+            checkpoint_interval: 300.0,
+            resume: false,
+            stop_after_passes: None,
+            workers: Vec::new(),
+            worker_timeout: 300.0,
+            serve: None,
+            animation: None,
+            frame: 0,
+            frames: None,
+            args: Vec::new()
+        }
+    }
+}
+
+impl Options {
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options { args: args.collect(), ..Options::default() };
+        let mut args = options.args.clone().into_iter();
+        let mut filter_name = String::from("box");
+        let mut filter_radius = None;
+        let mut camera_name = String::from("perspective");
+        let mut fisheye_fov = 180.0;
+        let mut height = None;
+        let mut stereo_layout = None;
+        let mut interocular = 0.065;
+        let mut convergence = None;
+        let mut blades = None;
+        let mut aperture_rotation = 0.0;
+        let mut physical = PhysicalCamera::default();
+        let mut is_physical = false;
+        let mut lens_elements = None;
+        let mut focal_length_set = false;
+        let mut f_stop_set = false;
+        let mut exposure_set = false;
+        let mut environment = None;
+        let mut environment_rotation = 0.0;
+        let mut environment_intensity = 1.0;
+        let mut use_sky = false;
+        let mut turbidity = 3.0;
+        let mut ground_albedo = 0.3;
+        let mut sun_elevation = 45.0;
+        let mut sun_azimuth = 135.0;
+        let mut sun_time = None;
+        let mut location = None;
+        let mut sky_intensity = 0.02;
+        // First of the flags that only mean something with --sky
+        let mut sky_flag = None;
+
+        while let Some(arg) = args.next() {
+            let sky_flags = ["--turbidity", "--ground-albedo", "--sun-elevation", "--sun-azimuth", "--sun-time", "--location", "--sky-intensity"];
+            if sky_flag.is_none() && sky_flags.contains(&arg.as_str()) {
+                sky_flag = Some(arg.clone());
+            }
+            let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+
+            match arg.as_str() {
+                "--width" => options.width = parse(&arg, &value()?)?,
+                "--height" => height = Some(parse(&arg, &value()?)?),
+                "--samples" => options.samples_per_pixel = parse(&arg, &value()?)?,
+                "--min-samples" => options.min_samples_per_pixel = parse(&arg, &value()?)?,
+                "--noise-threshold" => options.noise_threshold = parse(&arg, &value()?)?,
+                "--max-samples" => options.max_samples_per_pixel = Some(parse(&arg, &value()?)?),
+                "--pass-samples" => options.pass_samples = parse(&arg, &value()?)?,
+                "--preview-interval" => options.preview_interval = parse(&arg, &value()?)?,
+                "--max-depth" => options.max_depth = parse(&arg, &value()?)?,
+                "--camera" => camera_name = value()?,
+                "--fisheye-fov" => fisheye_fov = parse(&arg, &value()?)?,
+                "--stereo" => {
+                    let name = value()?;
+                    stereo_layout = Some(StereoLayout::from_name(&name)
+                        .ok_or(format!("unknown stereo layout {} (expected side-by-side or top-bottom)", name))?);
+                }
+                "--interocular" => interocular = parse(&arg, &value()?)?,
+                "--convergence" => convergence = Some(parse(&arg, &value()?)?),
+                "--aperture-blades" => blades = Some(parse(&arg, &value()?)?),
+                "--aperture-rotation" => aperture_rotation = parse(&arg, &value()?)?,
+                "--aperture-mask" => options.bokeh.shape = ApertureShape::load_mask(&value()?)?,
+                "--anamorphic" => options.bokeh.squeeze = parse(&arg, &value()?)?,
+                "--shift" => options.shift = parse_pair(&arg, &value()?)?,
+                "--tilt" => options.tilt = parse_pair(&arg, &value()?)?,
+                "--environment" => environment = Some(value()?),
+                "--environment-rotation" => environment_rotation = parse(&arg, &value()?)?,
+                "--environment-intensity" => environment_intensity = parse(&arg, &value()?)?,
+                "--emissive-spheres" => options.emissive_spheres = parse(&arg, &value()?)?,
+                "--light" => options.lights.push(Light::parse(&value()?)?),
+                "--metal" => {
+                    let value = value()?;
+                    options.metal = match value.as_str() {
+                        "fuzz" => None,
+                        _ => Some(MetalModel::parse(&value).ok_or(format!("unknown metal {}, expected fuzz, ggx, gold, copper, aluminium or silver", value))?)
+                    };
+                }
+                "--anisotropy" => options.anisotropy = parse(&arg, &value()?)?,
+                "--glass-roughness" => options.glass_roughness = parse(&arg, &value()?)?,
+                "--fresnel" => {
+                    let value = value()?;
+                    options.fresnel = Fresnel::parse(&value).ok_or(format!("unknown Fresnel term {}, expected schlick or exact", value))?;
+                }
+                "--sky" => use_sky = true,
+                "--turbidity" => turbidity = parse(&arg, &value()?)?,
+                "--ground-albedo" => ground_albedo = parse(&arg, &value()?)?,
+                "--sun-elevation" => sun_elevation = parse(&arg, &value()?)?,
+                "--sun-azimuth" => sun_azimuth = parse(&arg, &value()?)?,
+                "--sun-time" => sun_time = Some(parse_time(&value()?)?),
+                "--location" => location = Some(parse_pair(&arg, &value()?)?),
+                "--sky-intensity" => sky_intensity = parse(&arg, &value()?)?,
+                "--autofocus" => options.autofocus = Some(parse_focus_target(&value()?)?),
+                "--frame-objects" => options.framing = Some(parse_objects(&value()?)?),
+                "--vignetting" => options.bokeh.vignetting = parse(&arg, &value()?)?,
+                "--lens" => lens_elements = Some(Lens::load_elements(&value()?)?),
+                "--focal-length" | "--sensor" | "--f-stop" | "--shutter" | "--iso" | "--scene-scale" => {
+                    let value = value()?;
+                    match arg.as_str() {
+                        "--focal-length" => {
+                            physical.focal_length = parse(&arg, &value)?;
+                            focal_length_set = true;
+                        }
+                        "--sensor" => (physical.sensor_width, physical.sensor_height) = parse_sensor(&value)?,
+                        "--f-stop" => {
+                            physical.f_stop = parse(&arg, &value)?;
+                            f_stop_set = true;
+                            exposure_set = true;
+                        }
+                        "--shutter" => {
+                            physical.shutter = parse_shutter(&value)?;
+                            exposure_set = true;
+                        }
+                        "--iso" => {
+                            physical.iso = parse(&arg, &value)?;
+                            exposure_set = true;
+                        }
+                        _ => physical.units_per_meter = parse(&arg, &value)?
+                    }
+                    is_physical = true;
+                }
+                "--tile-size" => options.tile_size = parse(&arg, &value()?)?,
+                "--tile-order" => {
+                    let name = value()?;
+                    options.tile_order = TileOrder::from_name(&name)
+                        .ok_or(format!("unknown tile order {} (expected scanline, spiral or hilbert)", name))?;
+                }
+                "--crop" => options.crop = Some(parse_region(&value()?)?),
+                "--composite" => options.composite = Some(value()?),
+                "--filter" => filter_name = value()?,
+                "--filter-radius" => filter_radius = Some(parse(&arg, &value()?)?),
+                "--sampler" => {
+                    let name = value()?;
+                    options.sampler = Sampler::from_name(&name)
+                        .ok_or(format!("unknown sampler {} (expected independent, stratified, halton, sobol or bluenoise)", name))?;
+                }
+                "--seed" => options.seed = parse(&arg, &value()?)?,
+                "--output" => options.output = value()?,
+                "--heatmap" => options.heatmap = Some(value()?),
+                "--checkpoint" => options.checkpoint = Some(value()?),
+                "--checkpoint-interval" => options.checkpoint_interval = parse(&arg, &value()?)?,
+                "--resume" => options.resume = true,
+                "--stop-after-passes" => options.stop_after_passes = Some(parse(&arg, &value()?)?),
+                "--workers" => options.workers = value()?.split(',').map(|s| s.trim().to_string()).collect(),
+                "--worker-timeout" => options.worker_timeout = parse(&arg, &value()?)?,
+                "--serve" => options.serve = Some(value()?),
+                "--animation" => options.animation = Some(Animation::load(&value()?)?),
+                "--frame" => options.frame = parse(&arg, &value()?)?,
+                "--frames" => options.frames = Some(parse_frames(&value()?)?),
+                _ => return Err(format!("unknown argument {}", arg))
+            }
+        }
+
+        // An explicit height sets the aspect ratio, 2:1 suits equirectangular panoramas
+        match height {
+            Some(height) => {
+                options.height = height;
+                options.aspect_ratio = options.width as f64 / height as f64;
+            }
+            None => options.height = (options.width as f64 / options.aspect_ratio) as u32
+        }
+        // The cameras map pixels over width - 1 and height - 1
+        if options.width < 2 || options.height < 2 {
+            return Err(format!("the image must be at least 2x2 pixels, it is {}x{}", options.width, options.height));
+        }
+
+        if options.resume && options.checkpoint.is_none() {
+            return Err(String::from("--resume needs a --checkpoint file"));
+        }
+        if let Some(crop) = options.crop {
+            if crop.x0 >= crop.x1 || crop.y0 >= crop.y1 || crop.x1 > options.width || crop.y1 > options.height {
+                return Err(format!("--crop window must lie inside the {}x{} image", options.width, options.height));
+            }
+        }
+        else if options.composite.is_some() {
+            return Err(String::from("--composite needs a --crop window"));
+        }
+        if options.tile_size == 0 {
+            return Err(String::from("--tile-size must be at least 1"));
+        }
+        if options.pass_samples == 0 {
+            return Err(String::from("--pass-samples must be at least 1"));
+        }
+        if options.worker_timeout <= 0.0 {
+            return Err(String::from("--worker-timeout must be positive"));
+        }
+        if options.stop_after_passes.is_some() && options.checkpoint.is_none() {
+            return Err(String::from("--stop-after-passes needs a --checkpoint to resume from"));
+        }
+        // The minimum only matters when pixels may stop early
+        if options.noise_threshold > 0.0 && options.min_samples_per_pixel > options.samples_per_pixel {
+            return Err(String::from("--min-samples must not exceed --samples"));
+        }
+        if let Some(max) = options.max_samples_per_pixel {
+            if options.noise_threshold <= 0.0 {
+                return Err(String::from("--max-samples needs a --noise-threshold to tell which pixels need more"));
+            }
+            if max < options.samples_per_pixel {
+                return Err(String::from("--max-samples must not be less than --samples"));
+            }
+        }
+
+        if fisheye_fov <= 0.0 || fisheye_fov > 360.0 {
+            return Err(String::from("--fisheye-fov must be in (0, 360] degrees"));
+        }
+        options.projection = Projection::from_name(&camera_name, fisheye_fov)
+            .ok_or(format!("unknown camera {} (expected perspective, orthographic, fisheye, fisheye-equisolid or equirectangular)", camera_name))?;
+
+        if interocular < 0.0 || convergence.is_some_and(|c: f64| c <= 0.0) {
+            return Err(String::from("--interocular must not be negative and --convergence must be positive"));
+        }
+        options.stereo = stereo_layout.map(|layout| StereoSettings { layout, interocular, convergence });
+
+        if let Some(blades) = blades {
+            if blades < 3 {
+                return Err(String::from("--aperture-blades must be at least 3"));
+            }
+            if matches!(options.bokeh.shape, ApertureShape::Mask(_)) {
+                return Err(String::from("--aperture-blades and --aperture-mask can't be combined"));
+            }
+            options.bokeh.shape = ApertureShape::Polygon { blades, rotation: degrees_to_radians(aperture_rotation) };
+        }
+        if options.bokeh.squeeze <= 0.0 || options.bokeh.vignetting < 0.0 {
+            return Err(String::from("--anamorphic must be positive and --vignetting must not be negative"));
+        }
+
+        if options.tilt.0.abs() >= 90.0 || options.tilt.1.abs() >= 90.0 {
+            return Err(String::from("--tilt angles must be less than 90 degrees"));
+        }
+
+        if !(0.0..=1.0).contains(&options.emissive_spheres) {
+            return Err(String::from("--emissive-spheres must be between 0 and 1"));
+        }
+
+        if !(0.0..1.0).contains(&options.anisotropy) {
+            return Err(String::from("--anisotropy must be at least 0 and less than 1"));
+        }
+        if options.anisotropy > 0.0 && options.metal.is_none() {
+            return Err(String::from("--anisotropy needs a GGX --metal"));
+        }
+
+        if !(0.0..=1.0).contains(&options.glass_roughness) {
+            return Err(String::from("--glass-roughness must be between 0 and 1"));
+        }
+
+        if environment_intensity < 0.0 {
+            return Err(String::from("--environment-intensity must not be negative"));
+        }
+        if let Some(path) = environment {
+            let map = EnvironmentMap::load(&path, environment_rotation, environment_intensity)?;
+            options.background = Background::Environment(Arc::new(map));
+        }
+
+        if use_sky {
+            if matches!(options.background, Background::Environment(_)) {
+                return Err(String::from("--sky and --environment can't be used together"));
+            }
+            if !(1.7..=10.0).contains(&turbidity) {
+                return Err(String::from("--turbidity must be between 1.7 and 10"));
+            }
+            if !(0.0..=1.0).contains(&ground_albedo) || sky_intensity < 0.0 {
+                return Err(String::from("--ground-albedo must be between 0 and 1 and --sky-intensity must not be negative"));
+            }
+            if let Some((year, month, day, hours)) = sun_time {
+                let (latitude, longitude) = location.ok_or("--sun-time needs a --location")?;
+                (sun_elevation, sun_azimuth) = sky::sun_position(year, month, day, hours, latitude, longitude);
+            }
+            if !(0.0..=90.0).contains(&sun_elevation) {
+                return Err(format!("the sun must be above the horizon, its elevation is {:.1} degrees", sun_elevation));
+            }
+            let sky = Sky::new(turbidity, ground_albedo, sun_elevation, sun_azimuth, sky_intensity);
+            options.background = Background::Sky(Arc::new(sky));
+        } else if let Some(flag) = sky_flag {
+            return Err(format!("{} needs --sky", flag));
+        }
+
+        if let Some(FocusTarget::Pixel(x, y)) = options.autofocus {
+            if x >= options.width || y >= options.height {
+                return Err(format!("--autofocus pixel must lie inside the {}x{} image", options.width, options.height));
+            }
+        }
+
+        if is_physical {
+            let values = [physical.focal_length, physical.sensor_width, physical.sensor_height, physical.f_stop,
+                physical.shutter, physical.iso, physical.units_per_meter];
+            if values.iter().any(|&v| v <= 0.0) {
+                return Err(String::from("physical camera settings must be positive"));
+            }
+            options.physical = Some(physical);
+        }
+        if let Some(elements) = lens_elements {
+            // The prescription fixes the focal length and how the image is projected
+            if focal_length_set {
+                return Err(String::from("--focal-length can't be used with --lens, the prescription sets it"));
+            }
+            if options.projection != Projection::Perspective {
+                return Err(format!("--camera {} can't be used with --lens", camera_name));
+            }
+            let mut lens = Lens {
+                elements: Arc::new(elements),
+                sensor_width: physical.sensor_width,
+                sensor_height: physical.sensor_height,
+                units_per_meter: physical.units_per_meter
+            };
+            if f_stop_set {
+                lens.stop_down(physical.f_stop).map_err(|err| format!("--f-stop {}: {}", physical.f_stop, err))?;
+            } else if let Some(f_stop) = lens.f_stop() {
+                // Wide open
+                physical.f_stop = f_stop;
+            }
+            options.lens = Some(lens);
+        }
+        // The lens and sensor alone leave the brightness alone
+        if exposure_set {
+            options.exposure = physical.exposure();
+        }
+
+        let radius = filter_radius.unwrap_or_else(|| Filter::default_radius(&filter_name));
+        if radius <= 0.0 {
+            return Err(String::from("--filter-radius must be positive"));
+        }
+        options.filter = Filter::from_name(&filter_name, radius)
+            .ok_or(format!("unknown filter {} (expected box, tent, gaussian, mitchell or lanczos)", filter_name))?;
+
+        Ok(options)
+    }
+
+    // Arguments describing the render job for a worker: everything except the
+    // distribution settings and what the coordinator alone writes and reads
+    // (output, checkpoints, previews), for the frame these options are for
+    pub fn job_args(&self) -> Vec<String> {
+        let local = [
+            "--workers", "--worker-timeout", "--serve", "--frame", "--frames",
+            "--output", "--heatmap", "--composite", "--preview-interval",
+            "--checkpoint", "--checkpoint-interval", "--resume", "--stop-after-passes"
+        ];
+        let mut args = without_flags(&self.args, &local);
+        args.push(String::from("--frame"));
+        args.push(self.frame.to_string());
+        args
+    }
+
+    // Most samples any one pixel takes
+    pub fn max_samples(&self) -> u32 {
+        self.max_samples_per_pixel.unwrap_or(self.samples_per_pixel)
+    }
+
+    // Options for rendering one frame. The frames of a sequence write numbered files.
+    pub fn for_frame(&self, frame: u32) -> Options {
+        let mut options = Options { frame, ..self.clone() };
+        if self.frames.is_some() {
+            let numbered = |path: &str| frame_path(path, frame);
+            options.output = frame_path(&self.output, frame);
+            options.heatmap = self.heatmap.as_deref().map(numbered);
+            options.checkpoint = self.checkpoint.as_deref().map(numbered);
+            options.composite = self.composite.as_deref().map(numbered);
+        }
+        options
+    }
+
+    // Pixels that need samples: the crop window grown by the filter radius, so
+    // its edge pixels get the same contributions from outside as in a full render
+    pub fn render_region(&self) -> Region {
+        match self.crop {
+            Some(crop) => {
+                let margin = self.filter.radius().ceil() as u32;
+                Region::new(
+                    crop.x0.saturating_sub(margin),
+                    crop.y0.saturating_sub(margin),
+                    u32::min(crop.x1 + margin, self.width),
+                    u32::min(crop.y1 + margin, self.height)
+                )
+            }
+            None => Region::new(0, 0, self.width, self.height)
+        }
+    }
+}
+
+// Parses a crop window given as x0,y0,x1,y1
+fn parse_region(value: &str) -> Result<Region, String> {
+    let coords: Vec<u32> = value.split(',').map(|v| parse("--crop", v.trim())).collect::<Result<_, _>>()?;
+    match coords[..] {
+        [x0, y0, x1, y1] => Ok(Region::new(x0, y0, x1, y1)),
+        _ => Err(format!("invalid value {} for --crop, expected x0,y0,x1,y1", value))
+    }
+}
+
+// Parses two numbers given as x,y
+fn parse_pair(arg: &str, value: &str) -> Result<(f64, f64), String> {
+    let (x, y) = value.split_once(',').ok_or(format!("invalid value {} for {}, expected x,y", value, arg))?;
+    Ok((parse(arg, x.trim())?, parse(arg, y.trim())?))
+}
+
+// Parses a UTC date and time given as YYYY-MM-DDTHH:MM into the date and hours
+fn parse_time(value: &str) -> Result<(i32, u32, u32, f64), String> {
+    let invalid = || format!("invalid value {} for --sun-time, expected YYYY-MM-DDTHH:MM", value);
+    let (date, time) = value.split_once('T').ok_or_else(invalid)?;
+    let date: Vec<&str> = date.split('-').collect();
+    let (hour, minute) = time.split_once(':').ok_or_else(invalid)?;
+    if date.len() != 3 {
+        return Err(invalid());
+    }
+
+    let (year, month, day): (i32, u32, u32) = (parse("--sun-time", date[0])?, parse("--sun-time", date[1])?, parse("--sun-time", date[2])?);
+    let (hour, minute): (u32, u32) = (parse("--sun-time", hour)?, parse("--sun-time", minute)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return Err(invalid());
+    }
+    Ok((year, month, day, hour as f64 + minute as f64 / 60.0))
+}
+
+// Parses look-at or the x,y of a pixel
+fn parse_focus_target(value: &str) -> Result<FocusTarget, String> {
+    if value == "look-at" {
+        return Ok(FocusTarget::LookAt);
+    }
+    let (x, y) = value.split_once(',').ok_or(format!("invalid value {} for --autofocus, expected look-at or x,y", value))?;
+    Ok(FocusTarget::Pixel(parse("--autofocus", x.trim())?, parse("--autofocus", y.trim())?))
+}
+
+// Parses all or a comma separated list of object indices
+fn parse_objects(value: &str) -> Result<Vec<i64>, String> {
+    if value == "all" {
+        return Ok(Vec::new());
+    }
+    value.split(',').map(|index| parse("--frame-objects", index.trim())).collect()
+}
+
+// Parses a sensor size in millimeters given as widthxheight
+fn parse_sensor(value: &str) -> Result<(f64, f64), String> {
+    let (width, height) = value.split_once('x').ok_or(format!("invalid value {} for --sensor, expected widthxheight", value))?;
+    Ok((parse("--sensor", width.trim())?, parse("--sensor", height.trim())?))
+}
+
+// Parses a shutter time in seconds, given as a number or a fraction like 1/125
+fn parse_shutter(value: &str) -> Result<f64, String> {
+    match value.split_once('/') {
+        Some((numerator, denominator)) => {
+            let denominator: f64 = parse("--shutter", denominator.trim())?;
+            Ok(parse::<f64>("--shutter", numerator.trim())? / denominator)
+        }
+        None => parse("--shutter", value)
+    }
+}
+
+// Parses a frame range given as first-last, or a single frame
+fn parse_frames(value: &str) -> Result<RangeInclusive<u32>, String> {
+    let (first, last) = value.split_once('-').unwrap_or((value, value));
+    let (first, last) = (parse("--frames", first.trim())?, parse("--frames", last.trim())?);
+    if first > last {
+        return Err(format!("invalid value {} for --frames, the first frame comes after the last", value));
+    }
+    Ok(first..=last)
+}
+
+// Command line arguments without these flags and their values
+fn without_flags(args: &[String], flags: &[&str]) -> Vec<String> {
+    let mut kept = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if flags.contains(&arg.as_str()) {
+            // The switches don't take a value
+            if !["--resume", "--sky"].contains(&arg.as_str()) {
+                iter.next();
+            }
+            continue;
+        }
+        kept.push(arg.clone());
+    }
+    kept
+}
+
+// Path of one frame of a sequence: a run of '#' is replaced by the zero padded
+// frame number, without one the number is added before the extension
+fn frame_path(path: &str, frame: u32) -> String {
+    if let Some(start) = path.find('#') {
+        let len = path[start..].chars().take_while(|&c| c == '#').count();
+        return format!("{}{:0width$}{}", &path[..start], frame, &path[start + len..], width = len);
+    }
+    match path.rfind('.') {
+        Some(dot) if !path[dot..].contains('/') => format!("{}_{:04}{}", &path[..dot], frame, &path[dot..]),
+        _ => format!("{}_{:04}", path, frame)
+    }
+}
+
+fn parse<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value {} for {}", value, arg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Options;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn min_samples_only_bound_adaptive_sampling() {
+        assert!(parse(&["--samples", "4"]).is_ok());
+        assert!(parse(&["--samples", "4", "--noise-threshold", "0.01"]).is_err());
+        assert!(parse(&["--samples", "4", "--min-samples", "2", "--noise-threshold", "0.01"]).is_ok());
+    }
+
+    #[test]
+    fn job_leaves_out_what_only_the_coordinator_does() {
+        let options = parse(&[
+            "--width", "8", "--output", "a.png", "--heatmap", "h.png", "--checkpoint", "c.ckpt", "--resume",
+            "--stop-after-passes", "1", "--checkpoint-interval", "5", "--preview-interval", "2", "--sky",
+            "--workers", "host:1", "--frame", "3"
+        ]).unwrap();
+        assert_eq!(options.job_args(), ["--width", "8", "--sky", "--frame", "3"]);
+    }
+
+    #[test]
+    fn rejects_images_narrower_than_two_pixels() {
+        assert!(parse(&["--width", "1", "--height", "8"]).is_err());
+        assert!(parse(&["--width", "8", "--height", "1"]).is_err());
+        assert!(parse(&["--width", "2", "--height", "2"]).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_derived_height_below_two_pixels() {
+        assert!(parse(&["--width", "1"]).is_err());
+        // 3:2 by default
+        assert!(parse(&["--width", "2"]).is_err());
+        assert_eq!(parse(&["--width", "3"]).unwrap().height, 2);
+    }
+
+    #[test]
+    fn sky_settings_need_the_sky() {
+        for args in [["--turbidity", "4"], ["--ground-albedo", "0.5"], ["--sun-elevation", "30"], ["--sun-azimuth", "90"],
+            ["--sun-time", "2024-06-21T12:00"], ["--location", "52.5,13.4"], ["--sky-intensity", "0.05"]] {
+            let error = parse(&args).err().unwrap();
+            assert!(error.contains(args[0]) && error.contains("--sky"), "{}", error);
+            assert!(parse(&[&args[..], &["--sky", "--location", "52.5,13.4"]].concat()).is_ok(), "{:?}", args);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    io::Write,
+    time::{Duration, Instant}
+};
+
+const BAR_WIDTH: usize = 30;
+
+// Progress bar with ETA on stderr, counting finished tiles over all passes
+pub struct Progress {
+    start: Instant,
+    last_draw: Option<Instant>,
+    done: u64,
+    total: u64,
+    label: String
+}
+
+impl Progress {
+    pub fn new(total: u64) -> Progress {
+        Progress { start: Instant::now(), last_draw: None, done: 0, total, label: String::new() }
+    }
+
+    pub fn set_label(&mut self, label: String) {
+        self.label = label;
+    }
+
+    pub fn advance(&mut self) {
+        self.done += 1;
+
+        // Redrawing is cheap, but not for every tile of a small render
+        let now = Instant::now();
+        if self.done == self.total || self.last_draw.is_none_or(|t| now - t >= Duration::from_millis(100)) {
+            self.last_draw = Some(now);
+            self.draw();
+        }
+    }
+
+    pub fn finish(&self) {
+        eprintln!();
+    }
+
+    fn draw(&self) {
+        let fraction = if self.total == 0 { 1.0 } else { self.done as f64 / self.total as f64 };
+        let filled = (fraction * BAR_WIDTH as f64) as usize;
+        let elapsed = self.start.elapsed().as_secs_f64();
+
+        let eta = if self.done == 0 {
+            String::from("--")
+        }
+        else {
+            format_duration(elapsed * (1.0 - fraction) / fraction)
+        };
+
+        eprint!(
+            "\r[{}{}] {:5.1}% {} elapsed {} ETA {}   ",
+            "#".repeat(filled),
+            ".".repeat(BAR_WIDTH - filled),
+            100.0 * fraction,
+            self.label,
+            format_duration(elapsed),
+            eta
+        );
+        std::io::stderr().flush().unwrap();
+    }
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    if seconds >= 3600 {
+        format!("{}h{:02}m{:02}s", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    }
+    else {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    }
+}
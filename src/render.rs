@@ -0,0 +1,351 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    ops::Range,
+    sync::Mutex,
+    thread,
+    time::Instant
+};
+
+use rayon::prelude::*;
+
+use crate::{
+    background::Background,
+    camera::Camera,
+    checkpoint::{self, Checkpoint},
+    distributed::WorkerPool,
+    film::{Film, FilmTile, PixelStats},
+    hittable::{HitRecord, Hittable},
+    hittable_list::HittableList,
+    light::Light,
+    options::Options,
+    progress::Progress,
+    ray::Ray,
+    sampler::PixelSampler,
+    scene::Scene,
+    stats::{self, Counter},
+    tile::{self, Region},
+    vec3::{Color, Point3, Vec3}
+};
+
+// Renders the image progressively: every pass adds up to pass_samples samples
+// to each pixel that hasn't converged yet, and the image written so far is
+// refreshed every preview_interval seconds. Starts from `resume` if given.
+// Once every pixel has had samples_per_pixel, the ones that still haven't
+// converged share what the converged ones left of the image's budget, up to
+// max_samples_per_pixel each.
+pub fn render(scene: &Scene, cam: &Camera, options: &Options, resume: Option<Checkpoint>) -> Film {
+    let (mut film, first_pass) = match resume {
+        Some(checkpoint) => (checkpoint.film, checkpoint.next_pass),
+        None => (Film::new(options.width, options.height), 0)
+    };
+    let passes = options.samples_per_pixel.div_ceil(options.pass_samples)
+        + (options.max_samples() - options.samples_per_pixel).div_ceil(options.pass_samples);
+    let mut last_preview = Instant::now();
+    let mut last_checkpoint = Instant::now();
+
+    let tiles = tile::tiles(options.render_region(), options.tile_size, options.tile_order);
+    let mut workers = WorkerPool::connect(options);
+    let last_pass = options.stop_after_passes.map_or(passes, |n| u32::min(passes, first_pass.saturating_add(n)));
+    let mut progress = Progress::new(last_pass.saturating_sub(first_pass) as u64 * tiles.len() as u64);
+
+    for pass in first_pass..last_pass {
+        let Some(range) = pass_samples(&film, options, pass) else {
+            // The budget is spent
+            break;
+        };
+        progress.set_label(format!("pass {}/{}", pass + 1, passes));
+        let samples = render_pass(scene, cam, options, &mut film, &tiles, range, &mut workers, &mut progress);
+
+        let done = samples == 0 || pass + 1 == last_pass || pass_samples(&film, options, pass + 1).is_none();
+        if let Some(path) = &options.checkpoint {
+            if done || last_checkpoint.elapsed().as_secs_f64() >= options.checkpoint_interval {
+                if let Err(err) = checkpoint::save(path, &film, options, pass + 1) {
+                    eprintln!("Failed to write checkpoint {}: {}", path, err);
+                }
+                last_checkpoint = Instant::now();
+            }
+        }
+
+        if samples == 0 {
+            // Every pixel has converged
+            break;
+        }
+        if !done && last_preview.elapsed().as_secs_f64() >= options.preview_interval {
+            save_output(&film, options);
+            last_preview = Instant::now();
+        }
+    }
+    progress.finish();
+    film
+}
+
+// Indices of the samples pass `pass` takes, None once the budget is spent.
+// Past samples_per_pixel, the pixels still going share what is left of it.
+fn pass_samples(film: &Film, options: &Options, pass: u32) -> Option<Range<u32>> {
+    let regular_passes = options.samples_per_pixel.div_ceil(options.pass_samples);
+    if pass < regular_passes {
+        let first = pass * options.pass_samples;
+        return Some(first..u32::min(first + options.pass_samples, options.samples_per_pixel));
+    }
+
+    let region = options.render_region();
+    let budget = options.samples_per_pixel as u64 * region.width() as u64 * region.height() as u64;
+    let open = (region.y0..region.y1)
+        .flat_map(|y| (region.x0..region.x1).map(move |x| film.stats(x, y)))
+        .filter(|stats| stats.samples < options.max_samples() && !stats.converged(options.min_samples_per_pixel, options.noise_threshold))
+        .count() as u64;
+    let share = budget.saturating_sub(film.total_samples()).checked_div(open).unwrap_or(0);
+    let first = options.samples_per_pixel + (pass - regular_passes) * options.pass_samples;
+    let last = u32::min(first + options.pass_samples, options.max_samples());
+    (share > 0 && first < last).then(|| first..u32::min(last, first.saturating_add(u32::try_from(share).unwrap_or(u32::MAX))))
+}
+
+// Writes the image to options.output: the whole film, only the crop window,
+// or the crop window pasted into an earlier full render
+pub fn save_output(film: &Film, options: &Options) {
+    let crop = options.crop.unwrap_or(film.region());
+    match &options.composite {
+        Some(base) if options.crop.is_some() => {
+            let mut image = image::open(base).unwrap().to_rgb8();
+            let bytes = film.to_rgb8(crop);
+            for y in 0..crop.height() {
+                for x in 0..crop.width() {
+                    let i = 3 * (y * crop.width() + x) as usize;
+                    image.put_pixel(crop.x0 + x, crop.y0 + y, image::Rgb([bytes[i], bytes[i + 1], bytes[i + 2]]));
+                }
+            }
+            image.save(&options.output).unwrap();
+        }
+        _ => film.save(&options.output, crop)
+    }
+}
+
+// Adds the samples with indices in `samples` to the film, returns how many were taken
+#[allow(clippy::too_many_arguments)]
+fn render_pass(
+    scene: &Scene,
+    cam: &Camera,
+    options: &Options,
+    film: &mut Film,
+    tiles: &[Region],
+    samples: Range<u32>,
+    workers: &mut WorkerPool,
+    progress: &mut Progress
+) -> u64 {
+    let previous: Vec<PixelStats> = film.pixels().iter().map(|pixel| pixel.stats).collect();
+    let previous = &previous;
+    let previous_stats = |region: &Region| -> Vec<PixelStats> {
+        (region.y0..region.y1)
+            .flat_map(|y| (region.x0..region.x1).map(move |x| previous[(y * options.width + x) as usize]))
+            .collect()
+    };
+    let before = film.total_samples();
+
+    let merger = Mutex::new(TileMerger { film, progress, next: 0, pending: BTreeMap::new() });
+    let on_tile = |index: usize, tile: FilmTile| merger.lock().unwrap().add(index, tile);
+
+    // Local threads and remote workers take tiles from the same queue in list
+    // order, so the requested order is what shows up first in previews
+    let queue = Mutex::new((0..tiles.len()).collect::<VecDeque<usize>>());
+    let render_local = || (0..rayon::current_num_threads()).into_par_iter().for_each(|_| loop {
+        let Some(index) = queue.lock().unwrap().pop_front() else {
+            break;
+        };
+        let tile = render_tile(scene, cam, options, &tiles[index], &previous_stats(&tiles[index]), samples.clone());
+
+        stats::flush();
+        on_tile(index, tile);
+    });
+    thread::scope(|scope| {
+        if !workers.is_empty() {
+            scope.spawn(|| workers.render_tiles(tiles, samples.clone(), &queue, &previous_stats, &on_tile));
+        }
+        render_local();
+    });
+    // Tiles a lost worker put back after the local threads ran out of them
+    render_local();
+
+    let film = merger.into_inner().unwrap().film;
+    film.total_samples() - before
+}
+
+// Renders the samples in `samples` for the pixels of `region`, whose earlier
+// statistics are given row by row in `previous`. The tile includes the margin
+// its samples can reach, so contributions to pixels owned by neighbouring
+// tiles are summed rather than lost.
+pub fn render_tile(
+    scene: &Scene,
+    cam: &Camera,
+    options: &Options,
+    region: &Region,
+    previous: &[PixelStats],
+    samples: Range<u32>
+) -> FilmTile {
+    let mut tile = FilmTile::new(*region, options.width, options.height, &options.filter);
+    for y in region.y0..region.y1 {
+        for x in region.x0..region.x1 {
+            let stats = &previous[((y - region.y0) * region.width() + x - region.x0) as usize];
+            render_pixel(scene, cam, options, x, y, stats, samples.clone(), &mut tile);
+        }
+    }
+    tile
+}
+
+// Merges finished tiles into the film in tile order, whichever thread finishes
+// first. Neighbouring tiles overlap by the filter radius, and a fixed order keeps
+// the floating point sums independent of scheduling.
+struct TileMerger<'a> {
+    film: &'a mut Film,
+    progress: &'a mut Progress,
+    next: usize,
+    pending: BTreeMap<usize, FilmTile>
+}
+
+impl TileMerger<'_> {
+    fn add(&mut self, index: usize, tile: FilmTile) {
+        self.pending.insert(index, tile);
+        while let Some(tile) = self.pending.remove(&self.next) {
+            self.film.merge_tile(&tile);
+            self.progress.advance();
+            self.next += 1;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_pixel(
+    scene: &Scene,
+    cam: &Camera,
+    options: &Options,
+    x: u32,
+    y: u32,
+    previous: &PixelStats,
+    samples: Range<u32>,
+    tile: &mut FilmTile
+) {
+    // Statistics over all samples of the pixel so far, and over the ones taken in this pass
+    let mut stats = *previous;
+    let mut new_stats = PixelStats::default();
+
+    // With a noise threshold set, pixels stop as soon as their estimated error
+    // drops below it
+    for sample in samples {
+        if stats.converged(options.min_samples_per_pixel, options.noise_threshold) {
+            break;
+        }
+
+        // Every sample gets its own stream, so results don't depend on scheduling
+        let mut sampler = PixelSampler::new(options.sampler, options.seed, x, y, options.width, sample, options.max_samples());
+
+        // Raster position of the sample, row 0 is the top of the image
+        let (dx, dy) = sampler.get_2d();
+        let px = x as f64 + dx;
+        let py = y as f64 + dy;
+
+        let (u, v) = options.projection.image_position(px, py, options.width, options.height);
+        let color = match cam.sample_ray(u, v, &mut sampler) {
+            Some((r, weight)) => {
+                stats::count(Counter::CameraRays);
+                weight * options.exposure * ray_color(&r, scene, &options.lights, &options.background, options.max_depth, &mut sampler)
+            }
+            // Outside the part of the image the camera covers, or blocked by the lens barrel
+            None => Color::new(0.0, 0.0, 0.0)
+        };
+
+        tile.add_sample(px, py, color, &options.filter);
+
+        stats.add(color.luminance());
+        new_stats.add(color.luminance());
+    }
+
+    tile.add_stats(x, y, &new_stats);
+}
+
+// Radiance arriving along r. Surfaces that aren't mirrors are also lit
+// directly by the lights, and by sampling the glowing objects and the
+// background, the latter two combined with the light found by scattering
+// through multiple importance sampling.
+pub fn ray_color(r: &Ray, scene: &Scene, lights: &[Light], background: &Background, depth: u32, sampler: &mut PixelSampler) -> Color {
+    let mut color = Color::default();
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut ray = *r;
+    // Where the last bounce scattered from and the density of the direction
+    // it took, None when lights weren't sampled there
+    let mut last_scatter: Option<(Point3, f64)> = None;
+
+    for _ in 0..depth {
+        let mut rec = HitRecord::default();
+        if !scene.world.hit(&ray, 0.001, f64::INFINITY, &mut rec) {
+            let weight = match last_scatter {
+                Some((_, pdf)) if background.is_sampled() => power_heuristic(pdf, background.pdf(&ray.direction())),
+                _ => 1.0
+            };
+            color += weight * throughput * background.radiance(&ray.direction());
+            break;
+        }
+
+        let emitted = rec.material.emitted(&rec);
+        if emitted != Color::default() {
+            let weight = match last_scatter {
+                Some((p, pdf)) if !scene.emitters.is_empty() => power_heuristic(pdf, scene.emitters.pdf(&p, &rec.p)),
+                _ => 1.0
+            };
+            color += weight * throughput * emitted;
+        }
+
+        if !rec.material.is_specular() {
+            for light in lights {
+                let sample = light.sample(&rec.p, sampler.get_2d());
+                let f = rec.material.eval(&ray, &rec, &sample.direction);
+                if f != Color::default() && sample.radiance != Color::default() && visible(&scene.world, &rec.p, &sample.direction, sample.distance) {
+                    color += throughput * f * sample.radiance;
+                }
+            }
+
+            if !scene.emitters.is_empty() {
+                let u_select = sampler.get_1d();
+                if let Some((sample, light_pdf)) = scene.emitters.sample(&rec.p, u_select, sampler.get_2d()) {
+                    let f = rec.material.eval(&ray, &rec, &sample.direction);
+                    if f != Color::default() && visible(&scene.world, &rec.p, &sample.direction, sample.distance) {
+                        let weight = power_heuristic(light_pdf, rec.material.pdf(&ray, &rec, &sample.direction));
+                        color += weight / light_pdf * throughput * f * sample.radiance;
+                    }
+                }
+            }
+
+            // Only a sampled background takes sampler dimensions, so the plain gradient renders as before
+            if let Some((direction, radiance, light_pdf)) = background.is_sampled().then(|| background.sample(sampler.get_2d())).flatten() {
+                let f = rec.material.eval(&ray, &rec, &direction);
+                if light_pdf > 0.0 && f != Color::default() && visible(&scene.world, &rec.p, &direction, f64::INFINITY) {
+                    let weight = power_heuristic(light_pdf, rec.material.pdf(&ray, &rec, &direction));
+                    color += weight / light_pdf * throughput * f * radiance;
+                }
+            }
+        }
+
+        let mut scattered = Ray::default();
+        let mut attenuation = Color::default();
+        if !rec.material.scatter(&ray, &rec, &mut attenuation, &mut scattered, sampler) {
+            break;
+        }
+        stats::count(Counter::SecondaryRays);
+
+        throughput = throughput * attenuation;
+        last_scatter = (!rec.material.is_specular())
+            .then(|| (rec.p, rec.material.pdf(&ray, &rec, &scattered.direction())));
+        ray = scattered;
+    }
+    color
+}
+
+// Shadow ray test, whether nothing is in the way for the distance along the direction
+fn visible(world: &HittableList, p: &Point3, direction: &Vec3, distance: f64) -> bool {
+    stats::count(Counter::ShadowRays);
+    let mut rec = HitRecord::default();
+    !world.hit(&Ray::new(*p, *direction), 0.001, distance - 0.001, &mut rec)
+}
+
+// Weight of a sample from the strategy with density f against one with density g
+fn power_heuristic(f: f64, g: f64) -> f64 {
+    if f == 0.0 { 0.0 } else { f*f / (f*f + g*g) }
+}
@@ -0,0 +1,106 @@
+// PCG32 random number generator (pcg-random.org). Small, fast and fully
+// deterministic, so every sample can be given its own seeded stream and
+// renders come out bit-identical no matter how the work is scheduled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rng {
+    state: u64,
+    inc: u64
+}
+
+const MULTIPLIER: u64 = 6364136223846793005;
+
+impl Rng {
+    pub fn new(seed: u64, stream: u64) -> Rng {
+        let mut rng = Rng { state: 0, inc: (stream << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    // Independent generator for one sample of one pixel
+    pub fn for_sample(seed: u64, pixel: u64, sample: u64) -> Rng {
+        Rng::new(mix(mix(seed ^ mix(pixel)) ^ sample), seed)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(MULTIPLIER).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    // returns random f64 [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        // 53 random bits from two outputs
+        let hi = (self.next_u32() >> 5) as u64;
+        let lo = (self.next_u32() >> 6) as u64;
+        ((hi << 26) | lo) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+
+    // returns random f64 [min, max)
+    pub fn random_double(&mut self, min: f64, max: f64) -> f64 {
+        min + (max - min) * self.next_f64()
+    }
+}
+
+// SplitMix64 finalizer, turns structured inputs (pixel indices etc.) into well spread seeds
+pub fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rng;
+    use crate::{render::render, test_scene};
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut a = Rng::for_sample(7, 1234, 3);
+        let mut b = Rng::for_sample(7, 1234, 3);
+
+        for _ in 0..100 {
+            assert_eq!(a.next_u32(), b.next_u32());
+        }
+    }
+
+    #[test]
+    fn different_samples_differ() {
+        let mut a = Rng::for_sample(7, 1234, 3);
+        let mut b = Rng::for_sample(7, 1234, 4);
+
+        assert_ne!(a.next_u32(), b.next_u32());
+    }
+
+    #[test]
+    fn range() {
+        let mut rng = Rng::new(42, 0);
+
+        for _ in 0..10000 {
+            let x = rng.random_double(-1.0, 2.0);
+            assert!((-1.0..2.0).contains(&x));
+        }
+    }
+
+    #[test]
+    fn same_seed_is_independent_of_thread_count() {
+        let (scene, cam, options) = test_scene::glass_sphere();
+        let render_with = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| render(&scene, &cam, &options, None))
+        };
+
+        let single = render_with(1);
+        let multi = render_with(4);
+
+        for y in 0..options.height {
+            for x in 0..options.width {
+                assert_eq!(single.pixel_color(x, y), multi.pixel_color(x, y));
+            }
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use std::sync::OnceLock;
+
+use crate::rng::{Rng, mix};
+
+// Strategy used to place the samples of a pixel. Every sample of a path draws
+// from numbered dimensions: the pixel position first, then the lens, then the
+// BSDF samples of each bounce.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Sampler {
+    #[default]
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+    BlueNoise
+}
+
+impl Sampler {
+    pub fn from_name(name: &str) -> Option<Sampler> {
+        match name {
+            "independent" => Some(Sampler::Independent),
+            "stratified" => Some(Sampler::Stratified),
+            "halton" => Some(Sampler::Halton),
+            "sobol" => Some(Sampler::Sobol),
+            "bluenoise" => Some(Sampler::BlueNoise),
+            _ => None
+        }
+    }
+}
+
+// Sample stream for a single sample of a single pixel
+pub struct PixelSampler {
+    sampler: Sampler,
+    x: u32,
+    y: u32,
+    pixel_seed: u64,
+    index: u32,
+    samples_per_pixel: u32,
+    dimension: u32,
+    pub rng: Rng
+}
+
+impl PixelSampler {
+    pub fn new(sampler: Sampler, seed: u64, x: u32, y: u32, width: u32, index: u32, samples_per_pixel: u32) -> PixelSampler {
+        let pixel = y as u64 * width as u64 + x as u64;
+        PixelSampler {
+            sampler,
+            x, y,
+            pixel_seed: mix(seed ^ mix(pixel)),
+            index,
+            samples_per_pixel,
+            dimension: 0,
+            rng: Rng::for_sample(seed, pixel, index as u64)
+        }
+    }
+
+    pub fn get_1d(&mut self) -> f64 {
+        self.get_2d().0
+    }
+
+    pub fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        let dimension_seed = mix(self.pixel_seed ^ mix(dimension as u64));
+
+        match self.sampler {
+            Sampler::Independent => (self.rng.next_f64(), self.rng.next_f64()),
+            Sampler::Stratified => self.stratified(dimension_seed),
+            Sampler::Halton => self.halton(dimension, dimension_seed),
+            Sampler::Sobol => {
+                let index = nested_uniform_scramble(self.index, dimension_seed as u32);
+                let (x, y) = sobol_2d(index);
+                (
+                    to_unit(nested_uniform_scramble(x, (dimension_seed >> 32) as u32)),
+                    to_unit(nested_uniform_scramble(y, mix(dimension_seed) as u32))
+                )
+            }
+            Sampler::BlueNoise => {
+                // The same Sobol points in every pixel, toroidally shifted by a
+                // blue noise mask so the error between neighbouring pixels is
+                // pushed to high frequencies.
+                let index = nested_uniform_scramble(self.index, mix(dimension as u64) as u32);
+                let (x, y) = sobol_2d(index);
+                let shift = mix(dimension as u64);
+                let dx = blue_noise(self.x.wrapping_add(shift as u32), self.y.wrapping_add((shift >> 16) as u32));
+                let dy = blue_noise(self.x.wrapping_add((shift >> 32) as u32), self.y.wrapping_add((shift >> 48) as u32));
+                ((to_unit(x) + dx).fract(), (to_unit(y) + dy).fract())
+            }
+        }
+    }
+
+    // Jittered sample in a randomly permuted stratum of an nx * ny grid
+    fn stratified(&mut self, dimension_seed: u64) -> (f64, f64) {
+        let nx = f64::sqrt(self.samples_per_pixel as f64).floor().max(1.0) as u32;
+        let ny = self.samples_per_pixel.div_ceil(nx);
+        let stratum = permutation_element(self.index % (nx * ny), nx * ny, dimension_seed as u32);
+
+        let x = (stratum % nx) as f64 + self.rng.next_f64();
+        let y = (stratum / nx) as f64 + self.rng.next_f64();
+        (x / nx as f64, y / ny as f64)
+    }
+
+    // Halton points with a per pixel Cranley-Patterson rotation
+    fn halton(&mut self, dimension: u32, dimension_seed: u64) -> (f64, f64) {
+        let base = 2 * dimension as usize;
+        if base + 1 >= PRIMES.len() {
+            return (self.rng.next_f64(), self.rng.next_f64());
+        }
+        let offset_x = to_unit(dimension_seed as u32);
+        let offset_y = to_unit((dimension_seed >> 32) as u32);
+        (
+            (radical_inverse(self.index, PRIMES[base]) + offset_x).fract(),
+            (radical_inverse(self.index, PRIMES[base + 1]) + offset_y).fract()
+        )
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131
+];
+
+fn radical_inverse(mut index: u32, base: u32) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_n = 1.0;
+    let mut reversed = 0u64;
+    while index > 0 {
+        reversed = reversed * base as u64 + (index % base) as u64;
+        inv_base_n *= inv_base;
+        index /= base;
+    }
+    f64::min(reversed as f64 * inv_base_n, 1.0 - f64::EPSILON)
+}
+
+// First two dimensions of the Sobol sequence, a (0, 2)-sequence in base 2
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let mut v = 1u32 << 31;
+    let mut y = 0;
+    let mut i = index;
+    while i != 0 {
+        if i & 1 != 0 {
+            y ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    (index.reverse_bits(), y)
+}
+
+fn to_unit(x: u32) -> f64 {
+    x as f64 / 4294967296.0
+}
+
+// Hash based Owen scrambling (Burley 2020, "Practical Hash-based Owen Scrambling")
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+// Element i of a random permutation of [0, l) (Kensler 2013, "Correlated Multi-Jittered Sampling")
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    i.wrapping_add(p) % l
+}
+
+const BLUE_NOISE_SIZE: u32 = 64;
+
+// Value of the tiling blue noise mask in [0, 1)
+fn blue_noise(x: u32, y: u32) -> f64 {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    let mask = MASK.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE as usize));
+    mask[((y % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE + x % BLUE_NOISE_SIZE) as usize]
+}
+
+// Generates a size * size blue noise dither mask (Ulichney 1993, "The void-and-cluster method")
+fn void_and_cluster(size: usize) -> Vec<f64> {
+    let n = size * size;
+    let sigma = 1.5;
+
+    // Toroidal gaussian energy contributed by a point at offset (dx, dy)
+    let mut kernel = vec![0.0; n];
+    for dy in 0..size {
+        for dx in 0..size {
+            let wx = usize::min(dx, size - dx) as f64;
+            let wy = usize::min(dy, size - dy) as f64;
+            kernel[dy * size + dx] = f64::exp(-(wx*wx + wy*wy) / (2.0 * sigma*sigma));
+        }
+    }
+    let splat = |energy: &mut Vec<f64>, p: usize, sign: f64| {
+        let (px, py) = (p % size, p / size);
+        for y in 0..size {
+            for x in 0..size {
+                let k = kernel[((y + size - py) % size) * size + (x + size - px) % size];
+                energy[y * size + x] += sign * k;
+            }
+        }
+    };
+    let tightest_cluster = |energy: &Vec<f64>, pattern: &Vec<bool>| {
+        (0..n).filter(|&i| pattern[i]).max_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+    };
+    let largest_void = |energy: &Vec<f64>, pattern: &Vec<bool>| {
+        (0..n).filter(|&i| !pattern[i]).min_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+    };
+
+    // Random initial pattern with 10% of the pixels set
+    let mut rng = Rng::new(0x5eed, 0);
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0.0; n];
+    let initial = n / 10;
+    let mut count = 0;
+    while count < initial {
+        let p = (rng.next_u32() as usize) % n;
+        if !pattern[p] {
+            pattern[p] = true;
+            splat(&mut energy, p, 1.0);
+            count += 1;
+        }
+    }
+
+    // Move points from the tightest cluster to the largest void until stable
+    loop {
+        let cluster = tightest_cluster(&energy, &pattern);
+        pattern[cluster] = false;
+        splat(&mut energy, cluster, -1.0);
+        let void = largest_void(&energy, &pattern);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; n];
+
+    // Rank the initial points by removing tightest clusters
+    let mut phase_pattern = pattern.clone();
+    let mut phase_energy = energy.clone();
+    for r in (0..initial).rev() {
+        let cluster = tightest_cluster(&phase_energy, &phase_pattern);
+        phase_pattern[cluster] = false;
+        splat(&mut phase_energy, cluster, -1.0);
+        rank[cluster] = r;
+    }
+
+    // Rank the remaining pixels by filling the largest voids
+    for r in initial..n {
+        let void = largest_void(&energy, &pattern);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        rank[void] = r;
+    }
+
+    rank.iter().map(|&r| (r as f64 + 0.5) / n as f64).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PixelSampler, Sampler, void_and_cluster};
+
+    // Every sampler must cover the 2D domain evenly
+    fn check_uniform(sampler: Sampler) {
+        let samples = 256;
+        let mut bins = [0; 16];
+        for index in 0..samples {
+            let mut pixel = PixelSampler::new(sampler, 1, 3, 5, 16, index, samples);
+            let (u, v) = pixel.get_2d();
+            assert!((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v));
+            bins[(u * 4.0) as usize * 4 + (v * 4.0) as usize] += 1;
+        }
+        for count in bins {
+            assert!((8..=24).contains(&count), "{:?}: {:?}", sampler, bins);
+        }
+    }
+
+    #[test]
+    fn uniform() {
+        check_uniform(Sampler::Independent);
+        check_uniform(Sampler::Stratified);
+        check_uniform(Sampler::Halton);
+        check_uniform(Sampler::Sobol);
+        check_uniform(Sampler::BlueNoise);
+    }
+
+    #[test]
+    fn void_and_cluster_ranks_spread_out() {
+        let size = 16;
+        let mask = void_and_cluster(size);
+
+        // Every threshold is used once
+        let mut ranks: Vec<usize> = mask.iter().map(|&value| (value * (size * size) as f64) as usize).collect();
+        ranks.sort();
+        assert!(ranks.iter().enumerate().all(|(i, &rank)| i == rank));
+
+        // The first pixels to switch on keep their distance from each other on the torus
+        let first: Vec<usize> = (0..size * size).filter(|&i| mask[i] < 1.0 / 16.0).collect();
+        for &a in &first {
+            for &b in &first {
+                let dx = (a % size).abs_diff(b % size);
+                let dy = (a / size).abs_diff(b / size);
+                let (dx, dy) = (usize::min(dx, size - dx), usize::min(dy, size - dy));
+                assert!(a == b || dx * dx + dy * dy >= 4, "{} {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn low_discrepancy_samplers_are_stratified() {
+        // 16 Owen scrambled Sobol points fall one per cell of a 4x4 grid
+        let mut cells = [false; 16];
+        for index in 0..16 {
+            let mut pixel = PixelSampler::new(Sampler::Sobol, 9, 0, 0, 1, index, 16);
+            pixel.get_2d();
+            let (u, v) = pixel.get_2d();
+            let cell = (u * 4.0) as usize * 4 + (v * 4.0) as usize;
+            assert!(!cells[cell]);
+            cells[cell] = true;
+        }
+    }
+}
@@ -0,0 +1,7 @@
+use crate::{hittable_list::HittableList, light::tree::LightTree};
+
+// Everything rays can hit, with the glowing objects among it gathered for direct lighting
+pub struct Scene {
+    pub world: HittableList,
+    pub emitters: LightTree
+}
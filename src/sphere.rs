@@ -1,6 +1,6 @@
 use std::{sync::Arc};
 
-use crate::{hittable::{Hittable, HitRecord}, vec3::{Point3, Vec3}, ray::Ray, material::Material};
+use crate::{aabb::Aabb, hittable::{Hittable, HitRecord}, vec3::{Point3, Vec3}, ray::Ray, material::Material};
 
 pub struct Sphere {
     pub center: Point3,
@@ -38,6 +38,12 @@ impl Hittable for Sphere {
 
         true
     }
+
+    fn bounding_box(&self, output_box: &mut Aabb) -> bool {
+        let extent = Vec3::new(self.radius, self.radius, self.radius);
+        *output_box = Aabb::new(self.center - extent, self.center + extent);
+        true
+    }
 }
 
 impl Sphere {
@@ -0,0 +1,111 @@
+use std::{
+    cell::Cell,
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration
+};
+
+// Events counted during rendering. Counting goes to thread local cells, which
+// are only added to the global totals when a thread finishes a tile.
+#[derive(Debug, Clone, Copy)]
+pub enum Counter {
+    CameraRays,
+    SecondaryRays,
+    // Rays testing whether a light is visible
+    ShadowRays,
+    // Objects a ray is tested against. Without an acceleration structure this
+    // is the whole scene for every ray.
+    ObjectTests
+}
+
+const COUNTERS: usize = 4;
+
+static TOTALS: [AtomicU64; COUNTERS] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+thread_local! {
+    static LOCAL: [Cell<u64>; COUNTERS] = const { [Cell::new(0), Cell::new(0), Cell::new(0), Cell::new(0)] };
+}
+
+pub fn count(counter: Counter) {
+    add(counter, 1);
+}
+
+pub fn add(counter: Counter, n: u64) {
+    LOCAL.with(|local| {
+        let cell = &local[counter as usize];
+        cell.set(cell.get() + n);
+    });
+}
+
+// Adds this thread's counts to the totals
+pub fn flush() {
+    LOCAL.with(|local| {
+        for (total, cell) in TOTALS.iter().zip(local) {
+            total.fetch_add(cell.replace(0), Ordering::Relaxed);
+        }
+    });
+}
+
+fn total(counter: Counter) -> u64 {
+    TOTALS[counter as usize].load(Ordering::Relaxed)
+}
+
+// Summary printed at the end of a render. Counts are of this process only,
+// tiles rendered by workers are not in them.
+pub struct Report {
+    pub duration: Duration,
+    pub camera_rays: u64,
+    pub secondary_rays: u64,
+    pub shadow_rays: u64,
+    pub object_tests: u64,
+    pub peak_memory: Option<u64>,
+    // Whether workers rendered part of the image
+    pub distributed: bool
+}
+
+impl Report {
+    pub fn collect(duration: Duration, distributed: bool) -> Report {
+        flush();
+        Report {
+            duration,
+            camera_rays: total(Counter::CameraRays),
+            secondary_rays: total(Counter::SecondaryRays),
+            shadow_rays: total(Counter::ShadowRays),
+            object_tests: total(Counter::ObjectTests),
+            peak_memory: peak_memory(),
+            distributed
+        }
+    }
+
+    pub fn total_rays(&self) -> u64 {
+        self.camera_rays + self.secondary_rays + self.shadow_rays
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rays = self.total_rays();
+        let per_ray = |n: u64| if rays == 0 { 0.0 } else { n as f64 / rays as f64 };
+        let seconds = self.duration.as_secs_f64();
+
+        if self.distributed {
+            writeln!(f, "Counts below are of the tiles rendered locally, workers are not included")?;
+        }
+        writeln!(f, "Rays:               {} ({} camera, {} secondary, {} shadow)", rays, self.camera_rays, self.secondary_rays, self.shadow_rays)?;
+        writeln!(f, "Rays per second:    {:.0}", if seconds > 0.0 { rays as f64 / seconds } else { 0.0 })?;
+        writeln!(f, "Avg path length:    {:.2}", if self.camera_rays == 0 { 0.0 } else { (self.camera_rays + self.secondary_rays) as f64 / self.camera_rays as f64 })?;
+        writeln!(f, "Objects tested:     {:.1} per ray", per_ray(self.object_tests))?;
+        match self.peak_memory {
+            Some(bytes) => write!(f, "Peak memory:        {:.1} MiB", bytes as f64 / (1024.0 * 1024.0)),
+            None => write!(f, "Peak memory:        unknown")
+        }
+    }
+}
+
+// Peak resident set size, only available on Linux
+fn peak_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
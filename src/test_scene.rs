@@ -0,0 +1,27 @@
+use std::sync::Arc;
+
+use crate::{
+    camera::{Camera, perspective::Perspective},
+    hittable_list::HittableList,
+    light::tree::LightTree,
+    material::{Material, dielectric::Dielectric, lambertian::Lambertian},
+    options::Options,
+    scene::Scene,
+    sphere::Sphere,
+    vec3::{Color, Point3, Vec3}
+};
+
+// Small scene for the tests that render whole images: a glass sphere on the
+// ground, seen through a lens with some depth of field
+pub fn glass_sphere() -> (Scene, Camera, Options) {
+    let mut world = HittableList::new();
+    let ground = Arc::new(Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+    let glass = Arc::new(Material::Dielectric(Dielectric::new(1.5)));
+    world.add(Box::new(Sphere::new(Point3::new(0.0, -100.5, -1.0), 100.0, &ground)));
+    world.add(Box::new(Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, &glass)));
+
+    let options = Options { width: 24, height: 16, samples_per_pixel: 4, max_depth: 8, ..Options::default() };
+    let cam = Camera::Perspective(Perspective::new(Point3::new(0.0, 0.0, 1.0), Point3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0),
+        60.0, options.aspect_ratio, 0.1, 2.0));
+    (Scene { world, emitters: LightTree::default() }, cam, options)
+}
@@ -0,0 +1,180 @@
+// Rectangle of pixels [x0, x1) x [y0, y1), row 0 is the top of the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32
+}
+
+impl Region {
+    pub fn new(x0: u32, y0: u32, x1: u32, y1: u32) -> Region {
+        Region { x0, y0, x1, y1 }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+}
+
+// Order in which the tiles of an image are handed out to the render threads
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TileOrder {
+    Scanline,
+    // Outwards from the center of the image, where the subject usually is
+    Spiral,
+    // Along a Hilbert curve, consecutive tiles are always neighbours
+    #[default]
+    Hilbert
+}
+
+impl TileOrder {
+    pub fn from_name(name: &str) -> Option<TileOrder> {
+        match name {
+            "scanline" => Some(TileOrder::Scanline),
+            "spiral" => Some(TileOrder::Spiral),
+            "hilbert" => Some(TileOrder::Hilbert),
+            _ => None
+        }
+    }
+}
+
+// Splits `region` into tiles of at most tile_size * tile_size pixels, in the given order
+pub fn tiles(region: Region, tile_size: u32, order: TileOrder) -> Vec<Region> {
+    let nx = region.width().div_ceil(tile_size);
+    let ny = region.height().div_ceil(tile_size);
+
+    let tile = |tx: u32, ty: u32| {
+        let x0 = region.x0 + tx * tile_size;
+        let y0 = region.y0 + ty * tile_size;
+        Region::new(x0, y0, u32::min(x0 + tile_size, region.x1), u32::min(y0 + tile_size, region.y1))
+    };
+
+    let mut grid: Vec<(u32, u32)> = (0..ny).flat_map(|ty| (0..nx).map(move |tx| (tx, ty))).collect();
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => grid = spiral(nx, ny),
+        TileOrder::Hilbert => {
+            let n = u32::max(nx, ny).next_power_of_two();
+            grid.sort_by_key(|&(tx, ty)| hilbert_index(n, tx, ty));
+        }
+    }
+    grid.into_iter().map(|(tx, ty)| tile(tx, ty)).collect()
+}
+
+// Walks a square spiral around the center tile, keeping the cells inside the grid
+fn spiral(nx: u32, ny: u32) -> Vec<(u32, u32)> {
+    let total = (nx * ny) as usize;
+    let mut cells = Vec::with_capacity(total);
+    let (mut x, mut y) = ((nx as i64 - 1) / 2, (ny as i64 - 1) / 2);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut step = 1;
+    let mut direction = 0;
+
+    let visit = |x: i64, y: i64, cells: &mut Vec<(u32, u32)>| {
+        if x >= 0 && y >= 0 && x < nx as i64 && y < ny as i64 {
+            cells.push((x as u32, y as u32));
+        }
+    };
+    visit(x, y, &mut cells);
+    while cells.len() < total {
+        // Each step length is walked twice before it grows
+        for _ in 0..2 {
+            let (dx, dy) = directions[direction % 4];
+            for _ in 0..step {
+                x += dx;
+                y += dy;
+                visit(x, y, &mut cells);
+            }
+            direction += 1;
+        }
+        step += 1;
+    }
+    cells
+}
+
+// Position of cell (x, y) along the Hilbert curve filling an n * n grid, n a power of two
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        d += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+
+        // Rotate the quadrant so the curve stays connected
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tiles, Region, TileOrder};
+    use crate::{filter::Filter, render::render, test_scene};
+
+    #[test]
+    fn tiles_cover_region_once() {
+        let region = Region::new(3, 5, 103, 75);
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            let mut covered = vec![0; (region.x1 * region.y1) as usize];
+            for tile in tiles(region, 16, order) {
+                for y in tile.y0..tile.y1 {
+                    for x in tile.x0..tile.x1 {
+                        covered[(y * region.x1 + x) as usize] += 1;
+                    }
+                }
+            }
+            for y in 0..region.y1 {
+                for x in 0..region.x1 {
+                    let inside = x >= region.x0 && y >= region.y0;
+                    assert_eq!(covered[(y * region.x1 + x) as usize], inside as u32, "{:?}", order);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hilbert_tiles_are_neighbours() {
+        for pair in tiles(Region::new(0, 0, 64, 64), 8, TileOrder::Hilbert).windows(2) {
+            let dx = (pair[0].x0 as i64 - pair[1].x0 as i64).abs();
+            let dy = (pair[0].y0 as i64 - pair[1].y0 as i64).abs();
+            assert_eq!(dx + dy, 8);
+        }
+    }
+
+    #[test]
+    fn spiral_starts_in_center() {
+        let order = tiles(Region::new(0, 0, 50, 50), 10, TileOrder::Spiral);
+
+        assert_eq!(order[0], Region::new(20, 20, 30, 30));
+    }
+
+    #[test]
+    fn crop_matches_full_render() {
+        let (scene, cam, mut options) = test_scene::glass_sphere();
+        options.filter = Filter::from_name("gaussian", 1.5).unwrap();
+
+        let full = render(&scene, &cam, &options, None);
+        let crop = Region::new(5, 3, 13, 9);
+        options.crop = Some(crop);
+        let cropped = render(&scene, &cam, &options, None);
+
+        for y in crop.y0..crop.y1 {
+            for x in crop.x0..crop.x1 {
+                assert_eq!(full.pixel_color(x, y), cropped.pixel_color(x, y));
+            }
+        }
+    }
+}
@@ -1,16 +1,10 @@
 use std::f64::consts::PI;
-use rand::Rng;
 
 #[allow(dead_code)]
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
 
-// returns random f64 [min, max)
-pub fn random_double(min: f64, max: f64) -> f64 {
-    rand::thread_rng().gen_range(min..max)
-}
-
 pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
     if x < min {
         return min;
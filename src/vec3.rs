@@ -1,4 +1,6 @@
-use crate::util::{clamp, random_double};
+use std::f64::consts::PI;
+
+use crate::{util::clamp, rng::Rng};
 
 #[derive(Debug, PartialEq, Clone, Copy, Default)] //what is partialeq?
 pub struct Vec3 {
@@ -12,11 +14,11 @@ impl Vec3 {
         Vec3 {x, y, z}
     }
 
-    pub fn random(min: f64, max: f64) -> Vec3 {
+    pub fn random(rng: &mut Rng, min: f64, max: f64) -> Vec3 {
         Vec3::new(
-            random_double(min, max),
-            random_double(min, max),
-            random_double(min, max)
+            rng.random_double(min, max),
+            rng.random_double(min, max),
+            rng.random_double(min, max)
         )
     }
 
@@ -44,34 +46,58 @@ impl Vec3 {
         *self / self.length() // Todo is this right
     }
 
-    pub fn random_in_unit_sphere() -> Vec3 {
-        loop {
-            let p = Vec3::random(-1.0, 1.0);
-            if p.length_squared() < 1.0 {
-                return p;
-            }
-        }
+    // The random_* helpers below map uniform samples in [0, 1) to the target
+    // domain, so well distributed samples stay well distributed.
+
+    pub fn random_in_unit_sphere(sample: (f64, f64), radius_sample: f64) -> Vec3 {
+        radius_sample.cbrt() * Self::random_unit_vector(sample)
     }
 
-    pub fn random_unit_vector() -> Vec3 {
-        Self::random_in_unit_sphere().unit_vector()
+    pub fn random_unit_vector(sample: (f64, f64)) -> Vec3 {
+        let z = 1.0 - 2.0*sample.0;
+        let r = f64::sqrt(f64::max(0.0, 1.0 - z*z));
+        let phi = 2.0 * PI * sample.1;
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
     }
 
-    pub fn random_in_hemisphere(normal: &Vec3) -> Vec3 {
-        let in_unit_sphere = Self::random_in_unit_sphere();
-        if Vec3::dot(&in_unit_sphere, normal) > 0.0 {
-            return in_unit_sphere;
+    pub fn random_in_hemisphere(normal: &Vec3, sample: (f64, f64)) -> Vec3 {
+        let on_unit_sphere = Self::random_unit_vector(sample);
+        if Vec3::dot(&on_unit_sphere, normal) > 0.0 {
+            return on_unit_sphere;
         }
-        -in_unit_sphere
+        -on_unit_sphere
+    }
+
+    // Uniform over the directions within the cone around a unit axis
+    pub fn random_in_cone(axis: &Vec3, cos_theta_max: f64, sample: (f64, f64)) -> Vec3 {
+        let cos_theta = 1.0 - sample.0 * (1.0 - cos_theta_max);
+        let sin_theta = f64::sqrt(f64::max(0.0, 1.0 - cos_theta*cos_theta));
+        let phi = 2.0 * PI * sample.1;
+        let (tangent, bitangent) = axis.orthonormal_basis();
+        sin_theta*phi.cos()*tangent + sin_theta*phi.sin()*bitangent + cos_theta*(*axis)
     }
 
-    pub fn random_in_unit_disk() -> Vec3 {
-        loop {
-            let p = Vec3::new(random_double(-1.0, 1.0), random_double(-1.0, 1.0), 0.0);
-            if p.length_squared() < 1.0 {
-                return p;
-            }
+    // Two unit vectors perpendicular to this unit vector and each other
+    pub fn orthonormal_basis(&self) -> (Vec3, Vec3) {
+        let a = if self.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let tangent = Vec3::cross(self, &a).unit_vector();
+        (tangent, Vec3::cross(self, &tangent))
+    }
+
+    // Concentric mapping (Shirley & Chiu 1997), keeps strata intact
+    pub fn random_in_unit_disk(sample: (f64, f64)) -> Vec3 {
+        let a = 2.0*sample.0 - 1.0;
+        let b = 2.0*sample.1 - 1.0;
+        if a == 0.0 && b == 0.0 {
+            return Vec3::default();
+        }
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, PI / 4.0 * (b / a))
         }
+        else {
+            (b, PI / 2.0 - PI / 4.0 * (a / b))
+        };
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
     }
 
     // Return true if the vector is close to zero in all dimensions
@@ -207,6 +233,11 @@ pub type Point3 = Vec3;   // 3d point
 pub type Color = Vec3;    // rgb color
 
 impl Color {
+    // Relative luminance of a linear rgb color (Rec. 709)
+    pub fn luminance(&self) -> f64 {
+        0.2126*self.x + 0.7152*self.y + 0.0722*self.z
+    }
+
     pub fn translate(&self, samples_per_pixel: u32) -> Color {    // give better name
         let mut r = self.x;
         let mut g = self.y;